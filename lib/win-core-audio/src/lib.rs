@@ -718,6 +718,26 @@ impl WaveFormatExtensible {
     pub fn samples_per_sec(&self) -> u32 {
         unsafe { self.0.as_ref().nSamplesPerSec }
     }
+
+    /// Get the number of bytes in a frame
+    pub fn block_align(&self) -> u16 {
+        unsafe { self.0.as_ref().nBlockAlign }
+    }
+
+    /// Get the number of bits each sample takes up
+    pub fn bits_per_sample(&self) -> u16 {
+        unsafe { self.0.as_ref().wBitsPerSample }
+    }
+
+    /// Get the number of bits of each sample that are used if this is WAVE_FORMAT_EXTENSIBLE.
+    pub fn valid_bits_per_sample(&self) -> Option<u16> {
+        Some(self.as_raw_wave_format_extensible()?.Samples)
+    }
+
+    /// Get the speaker positions of the channels if this is WAVE_FORMAT_EXTENSIBLE.
+    pub fn channel_mask(&self) -> Option<u32> {
+        Some(self.as_raw_wave_format_extensible()?.dwChannelMask)
+    }
 }
 
 impl std::fmt::Debug for WaveFormatExtensible {
@@ -727,6 +747,10 @@ impl std::fmt::Debug for WaveFormatExtensible {
             .field("ks_data_format_type", &self.ks_data_format_type())
             .field("num_channels", &self.num_channels())
             .field("samples_per_sec", &self.samples_per_sec())
+            .field("block_align", &self.block_align())
+            .field("bits_per_sample", &self.bits_per_sample())
+            .field("valid_bits_per_sample", &self.valid_bits_per_sample())
+            .field("channel_mask", &self.channel_mask())
             .finish()
     }
 }
@@ -0,0 +1,322 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// A source of time that can be slept on
+pub trait Clock {
+    /// Get the time since the clock started.
+    fn now(&self) -> Duration;
+
+    /// Block until the clock reaches a time.
+    fn sleep_until(&self, time: Duration);
+}
+
+/// The wall clock
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    /// Make a new [`SystemClock`] that starts now.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep_until(&self, time: Duration) {
+        if let Some(duration) = time.checked_sub(self.now()) {
+            std::thread::sleep(duration);
+        }
+    }
+}
+
+/// A clock that only moves when it is slept on or advanced by hand.
+///
+/// Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    /// Make a new [`VirtualClock`] at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) += duration;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn sleep_until(&self, time: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now = (*now).max(time);
+    }
+}
+
+/// Get the time it takes to play some frames, rounded up to make sure they have all played.
+fn frames_to_duration(num_frames: u64, sample_rate: u32) -> Duration {
+    let sample_rate = u128::from(sample_rate);
+    let nanos = (u128::from(num_frames) * 1_000_000_000 + sample_rate - 1) / sample_rate;
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+/// Emulates a device playing from a ring buffer, like a shared-mode WASAPI endpoint.
+///
+/// Once started, the device takes one period of frames from the buffer at every period boundary,
+/// then wakes up the writer.
+/// Frames it takes when the buffer is empty are played as silence, and counted as an underrun.
+#[derive(Debug)]
+pub struct Playhead<C> {
+    clock: C,
+    sample_rate: u32,
+    buffer_frames: u32,
+    period_frames: u32,
+
+    /// When the device was started, by the clock
+    start: Option<Duration>,
+
+    /// The number of periods the device has played since it was started
+    num_periods: u64,
+
+    num_frames_written: u64,
+    num_frames_played: u64,
+    num_underrun_frames: u64,
+}
+
+impl<C> Playhead<C>
+where
+    C: Clock,
+{
+    /// Make a new [`Playhead`].
+    ///
+    /// # Errors
+    /// Returns an error if the rate or period is zero, or the period is bigger than the buffer.
+    pub fn new(
+        clock: C,
+        sample_rate: u32,
+        buffer_frames: u32,
+        period_frames: u32,
+    ) -> anyhow::Result<Self> {
+        if sample_rate == 0 {
+            anyhow::bail!("the sample rate must not be zero");
+        }
+        if period_frames == 0 || period_frames > buffer_frames {
+            anyhow::bail!(
+                "invalid period of {} frames for a buffer of {} frames",
+                period_frames,
+                buffer_frames
+            );
+        }
+
+        Ok(Self {
+            clock,
+            sample_rate,
+            buffer_frames,
+            period_frames,
+
+            start: None,
+            num_periods: 0,
+
+            num_frames_written: 0,
+            num_frames_played: 0,
+            num_underrun_frames: 0,
+        })
+    }
+
+    /// Get the clock.
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Get the number of frames the buffer holds.
+    pub fn buffer_frames(&self) -> u32 {
+        self.buffer_frames
+    }
+
+    /// Get the number of frames played every period.
+    pub fn period_frames(&self) -> u32 {
+        self.period_frames
+    }
+
+    /// Get the number of frames written but not played yet.
+    pub fn padding(&self) -> u32 {
+        (self.num_frames_written - self.num_frames_played) as u32
+    }
+
+    /// Get the number of written frames that were played.
+    pub fn num_frames_played(&self) -> u64 {
+        self.num_frames_played
+    }
+
+    /// Get the number of frames that were played as silence because the buffer was empty.
+    pub fn num_underrun_frames(&self) -> u64 {
+        self.num_underrun_frames
+    }
+
+    /// Return true if the device is playing.
+    pub fn is_started(&self) -> bool {
+        self.start.is_some()
+    }
+
+    /// Start playing at the current time.
+    pub fn start(&mut self) {
+        if self.start.is_none() {
+            self.start = Some(self.clock.now());
+            self.num_periods = 0;
+        }
+    }
+
+    /// Play every period that has ended by now, then stop.
+    ///
+    /// Written frames that were not played stay in the buffer.
+    pub fn stop(&mut self) {
+        self.update();
+        self.start = None;
+    }
+
+    /// Block until the end of the next period, and get how many frames are free.
+    ///
+    /// If the clock is already past the end of the next period, this returns at once,
+    /// like a late wake-up.
+    ///
+    /// # Errors
+    /// Returns an error if the device was not started, as it would never wake up.
+    pub fn wait(&mut self) -> anyhow::Result<u32> {
+        let start = match self.start {
+            Some(start) => start,
+            None => anyhow::bail!("the device was not started"),
+        };
+
+        let period_end = frames_to_duration(
+            (self.num_periods + 1) * u64::from(self.period_frames),
+            self.sample_rate,
+        );
+        self.clock.sleep_until(start + period_end);
+        self.update();
+
+        Ok(self.buffer_frames - self.padding())
+    }
+
+    /// Write frames into the free part of the buffer.
+    ///
+    /// # Errors
+    /// Returns an error if there is not enough free space.
+    pub fn write(&mut self, num_frames: u32) -> anyhow::Result<()> {
+        let num_free_frames = self.buffer_frames - self.padding();
+        if num_frames > num_free_frames {
+            anyhow::bail!(
+                "wrote {} frames, but only {} frames are free",
+                num_frames,
+                num_free_frames
+            );
+        }
+
+        self.num_frames_written += u64::from(num_frames);
+        Ok(())
+    }
+
+    /// Play every period that has ended by now.
+    fn update(&mut self) {
+        let start = match self.start {
+            Some(start) => start,
+            None => return,
+        };
+
+        let elapsed = self.clock.now().saturating_sub(start);
+        let elapsed_frames = elapsed.as_nanos() * u128::from(self.sample_rate) / 1_000_000_000;
+        let num_periods = (elapsed_frames / u128::from(self.period_frames)) as u64;
+
+        while self.num_periods < num_periods {
+            let padding = self.padding();
+            let num_frames = padding.min(self.period_frames);
+            self.num_frames_played += u64::from(num_frames);
+            self.num_underrun_frames += u64::from(self.period_frames - num_frames);
+            self.num_periods += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn virtual_clock_is_shared() {
+        let clock = VirtualClock::new();
+        let other = clock.clone();
+
+        clock.advance(Duration::from_millis(10));
+        other.sleep_until(Duration::from_millis(25));
+        assert_eq!(clock.now(), Duration::from_millis(25));
+
+        // Sleeping until the past does nothing.
+        other.sleep_until(Duration::from_millis(5));
+        assert_eq!(clock.now(), Duration::from_millis(25));
+    }
+
+    #[test]
+    fn playhead_plays_a_period_every_wake_up() {
+        let clock = VirtualClock::new();
+        let mut playhead =
+            Playhead::new(clock.clone(), 48000, 1000, 480).expect("invalid playhead");
+
+        playhead.write(1000).expect("failed to write");
+        assert!(playhead.write(1).is_err());
+        playhead.start();
+
+        assert_eq!(playhead.wait().expect("failed to wait"), 480);
+        assert_eq!(clock.now(), Duration::from_millis(10));
+        assert_eq!(playhead.wait().expect("failed to wait"), 960);
+        assert_eq!(clock.now(), Duration::from_millis(20));
+
+        // Only 40 frames are left for the third period.
+        assert_eq!(playhead.wait().expect("failed to wait"), 1000);
+        assert_eq!(playhead.num_underrun_frames(), 440);
+    }
+
+    #[test]
+    fn late_wake_ups_play_every_missed_period() {
+        let clock = VirtualClock::new();
+        let mut playhead =
+            Playhead::new(clock.clone(), 44100, 1000, 441).expect("invalid playhead");
+
+        playhead.write(1000).expect("failed to write");
+        playhead.start();
+
+        // A stall of 25 ms misses a wake-up, so the next one returns at once with 2 periods free.
+        clock.advance(Duration::from_millis(25));
+        assert_eq!(playhead.wait().expect("failed to wait"), 882);
+        assert_eq!(clock.now(), Duration::from_millis(25));
+        assert_eq!(playhead.num_underrun_frames(), 0);
+
+        playhead.stop();
+        assert!(playhead.wait().is_err());
+        assert_eq!(playhead.padding(), 118);
+    }
+}
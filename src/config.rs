@@ -0,0 +1,39 @@
+use crate::dither::DitherMode;
+use crate::mix::MixMatrix;
+use crate::resample::ResampleQuality;
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Settings loaded from a TOML config file.
+///
+/// Command line options override these.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// How to convert audio to each device's sample rate
+    pub resample_quality: Option<ResampleQuality>,
+
+    /// A matrix that mixes the input channels onto every device's channels,
+    /// with one row of gains per device channel
+    pub mix_matrix: Option<MixMatrix>,
+
+    /// How to dither audio for devices that play integer samples
+    pub dither: Option<DitherMode>,
+
+    /// A linear scale for the audio written to every device
+    pub gain: Option<f32>,
+}
+
+impl Config {
+    /// Load a config file.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be read or is invalid.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config '{}'", path.display()))?;
+        toml::from_str(&data)
+            .with_context(|| format!("failed to parse config '{}'", path.display()))
+    }
+}
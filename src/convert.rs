@@ -0,0 +1,595 @@
+use crate::decoder::AudioChunk;
+use crate::mix::MixMatrix;
+use crate::resample::ResampleQuality;
+use crate::resample::StreamingResampler;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::Weak;
+use symphonia::core::audio::Channels;
+use symphonia::core::audio::SignalSpec;
+
+/// The number of converted chunks kept for streams that lag behind the fastest one.
+///
+/// The fastest stream waits once this many chunks are buffered.
+const CONVERTED_CHUNK_CAPACITY: usize = 32;
+
+/// A device format that decoded audio is converted to
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionKey {
+    /// The sample rate
+    pub sample_rate: u32,
+
+    /// The number of interleaved channels
+    pub num_channels: usize,
+
+    /// The speaker positions of the channels, in order.
+    ///
+    /// Channels past these are silent.
+    pub channel_layout: Channels,
+
+    /// How to resample
+    pub quality: ResampleQuality,
+
+    /// A matrix to mix with instead of the standard one for the input's layout
+    pub mix_matrix: Option<Arc<MixMatrix>>,
+}
+
+/// A chunk of audio converted to a device format
+#[derive(Debug, Clone)]
+pub struct ConvertedChunk {
+    /// The seek generation of the decoded audio this was converted from
+    pub seek_generation: u64,
+
+    /// The interleaved samples
+    pub samples: Arc<Vec<f32>>,
+}
+
+/// A conversion in a [`ConversionCache`]
+type CachedConversion = (ConversionKey, Weak<Conversion>);
+
+/// The conversions of one decoder thread's audio, by format.
+///
+/// Conversions are dropped once no stream uses them.
+#[derive(Clone, Default)]
+pub struct ConversionCache {
+    conversions: Arc<Mutex<Vec<CachedConversion>>>,
+}
+
+impl ConversionCache {
+    /// Get the conversion to a format, or make it if no stream is using one.
+    pub fn get_or_insert_with<F>(&self, key: ConversionKey, make_conversion: F) -> Arc<Conversion>
+    where
+        F: FnOnce() -> Conversion,
+    {
+        let mut conversions = self.conversions.lock().unwrap_or_else(|e| e.into_inner());
+        conversions.retain(|(_, conversion)| conversion.strong_count() != 0);
+
+        let existing = conversions
+            .iter()
+            .filter(|(conversion_key, _)| *conversion_key == key)
+            .find_map(|(_, conversion)| conversion.upgrade());
+        if let Some(conversion) = existing {
+            return conversion;
+        }
+
+        let conversion = Arc::new(make_conversion());
+        conversions.push((key, Arc::downgrade(&conversion)));
+        conversion
+    }
+}
+
+/// Decoded audio converted to one device format.
+///
+/// Every stream that needs the same format shares one conversion,
+/// so the expensive resampling is only done once.
+/// One stream at a time converts the next chunks, without holding the lock,
+/// so streams that lag behind can keep reading the chunks that are already buffered.
+///
+/// Resampled audio is converted a block at a time as streams ask for it,
+/// rather than a whole decoded chunk at once.
+pub struct Conversion {
+    key: ConversionKey,
+    seek_generation: Arc<AtomicU64>,
+    state: Mutex<ConversionState>,
+    condvar: Condvar,
+
+    /// Only locked by the stream that is converting, so it is never waited on
+    converter: Mutex<Converter>,
+}
+
+struct ConversionState {
+    /// Whether a stream is converting the next chunks
+    is_converting: bool,
+    is_finished: bool,
+
+    chunks: VecDeque<ConvertedChunk>,
+    first_chunk_index: u64,
+    cursors: Vec<Option<u64>>,
+}
+
+/// Converts decoded chunks from a decoder thread
+struct Converter {
+    receiver: Receiver<(u64, Arc<AudioChunk>)>,
+    seek_generation: u64,
+    resampler: Option<(SignalSpec, StreamingResampler)>,
+    mix_matrix: Option<(Channels, Arc<MixMatrix>)>,
+}
+
+impl Conversion {
+    /// Make a new [`Conversion`] of the chunks from a decoder thread.
+    pub fn new(
+        receiver: Receiver<(u64, Arc<AudioChunk>)>,
+        seek_generation: Arc<AtomicU64>,
+        key: ConversionKey,
+    ) -> Self {
+        Self {
+            key,
+            seek_generation,
+            state: Mutex::new(ConversionState {
+                is_converting: false,
+                is_finished: false,
+
+                chunks: VecDeque::new(),
+                first_chunk_index: 0,
+                cursors: Vec::new(),
+            }),
+            condvar: Condvar::new(),
+
+            converter: Mutex::new(Converter {
+                receiver,
+                seek_generation: 0,
+                resampler: None,
+                mix_matrix: None,
+            }),
+        }
+    }
+
+    /// Get the format this converts to.
+    pub fn key(&self) -> &ConversionKey {
+        &self.key
+    }
+
+    /// Get the latest seek generation of the decoder thread.
+    pub fn seek_generation(&self) -> u64 {
+        self.seek_generation.load(Ordering::SeqCst)
+    }
+
+    /// Add a consumer, returning its id.
+    ///
+    /// It starts reading at the oldest chunk that is still buffered.
+    pub fn add_consumer(&self) -> usize {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let cursor = Some(state.first_chunk_index);
+
+        match state.cursors.iter().position(Option::is_none) {
+            Some(id) => {
+                state.cursors[id] = cursor;
+                id
+            }
+            None => {
+                state.cursors.push(cursor);
+                state.cursors.len() - 1
+            }
+        }
+    }
+
+    /// Remove a consumer, so that the others no longer wait for it.
+    pub fn remove_consumer(&self, id: usize) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.cursors[id] = None;
+        state.trim();
+        self.condvar.notify_all();
+    }
+
+    /// Get the next converted chunk for a consumer.
+    ///
+    /// This blocks until the chunk is converted, and skips chunks from before the latest seek.
+    /// Returns `None` once the decoder thread has exited and all of its audio was read.
+    ///
+    /// # Errors
+    /// Returns an error if resampling failed.
+    pub fn next_chunk(&self, id: usize) -> anyhow::Result<Option<ConvertedChunk>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let seek_generation = self.seek_generation();
+
+            let cursor = state.cursors[id].expect("missing consumer");
+            let index = usize::try_from(cursor - state.first_chunk_index).unwrap_or(usize::MAX);
+            if let Some(chunk) = state.chunks.get(index).cloned() {
+                state.cursors[id] = Some(cursor + 1);
+                state.trim();
+                self.condvar.notify_all();
+
+                if chunk.seek_generation < seek_generation {
+                    continue;
+                }
+                return Ok(Some(chunk));
+            }
+
+            if state.is_finished {
+                return Ok(None);
+            }
+
+            // Wait for lagging consumers instead of buffering without bound,
+            // and for the stream that is already converting.
+            if state.chunks.len() >= CONVERTED_CHUNK_CAPACITY || state.is_converting {
+                state = self.condvar.wait(state).unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+
+            state.is_converting = true;
+            drop(state);
+
+            let mut chunks = Vec::new();
+            let result = self
+                .converter
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .convert(&self.key, &self.seek_generation, &mut chunks);
+
+            state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.is_converting = false;
+            state.chunks.extend(chunks);
+            self.condvar.notify_all();
+            state.is_finished = result?;
+        }
+    }
+}
+
+impl ConversionState {
+    /// Drop the chunks every consumer has read.
+    fn trim(&mut self) {
+        let min_cursor = self.cursors.iter().flatten().min().copied();
+        while let Some(min_cursor) = min_cursor {
+            if self.first_chunk_index >= min_cursor || self.chunks.pop_front().is_none() {
+                break;
+            }
+            self.first_chunk_index += 1;
+        }
+    }
+}
+
+impl Converter {
+    /// Convert audio until at least one chunk is ready, blocking until it is decoded.
+    ///
+    /// Decoded chunks from before the latest seek are skipped.
+    /// Returns `true` once the decoder thread has exited and the rest of its audio was converted.
+    fn convert(
+        &mut self,
+        key: &ConversionKey,
+        seek_generation: &AtomicU64,
+        chunks: &mut Vec<ConvertedChunk>,
+    ) -> anyhow::Result<bool> {
+        while chunks.is_empty() {
+            if self.resample_block(key, chunks)? {
+                continue;
+            }
+
+            match self.receiver.recv() {
+                Ok((chunk_seek_generation, chunk)) => {
+                    if chunk_seek_generation < seek_generation.load(Ordering::SeqCst) {
+                        continue;
+                    }
+                    self.push_chunk(key, chunk_seek_generation, &chunk, chunks)?;
+                }
+                Err(_) => {
+                    self.flush_resampler(key, chunks)?;
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Convert a decoded chunk.
+    fn push_chunk(
+        &mut self,
+        key: &ConversionKey,
+        seek_generation: u64,
+        chunk: &AudioChunk,
+        chunks: &mut Vec<ConvertedChunk>,
+    ) -> anyhow::Result<()> {
+        // Audio from before a seek does not continue into audio after it.
+        if seek_generation > self.seek_generation {
+            self.seek_generation = seek_generation;
+            self.resampler = None;
+        }
+
+        // Flush the old resampler if the spec changed.
+        if self
+            .resampler
+            .as_ref()
+            .is_some_and(|(spec, _)| *spec != chunk.spec)
+        {
+            self.flush_resampler(key, chunks)?;
+        }
+
+        if chunk.spec.rate == key.sample_rate {
+            return self.push_samples(key, chunk.spec.channels, &chunk.samples, chunks);
+        }
+
+        if self.resampler.is_none() {
+            let resampler = StreamingResampler::new(
+                key.quality,
+                chunk.spec.rate,
+                key.sample_rate,
+                chunk.spec.channels.count(),
+            )?;
+            self.resampler = Some((chunk.spec, resampler));
+        }
+
+        let (_, resampler) = self.resampler.as_mut().expect("missing resampler");
+        resampler.push(&chunk.samples);
+
+        Ok(())
+    }
+
+    /// Resample the next block of queued audio.
+    ///
+    /// Returns `false` if the resampler needs another decoded chunk.
+    fn resample_block(
+        &mut self,
+        key: &ConversionKey,
+        chunks: &mut Vec<ConvertedChunk>,
+    ) -> anyhow::Result<bool> {
+        let (spec, resampler) = match self.resampler.as_mut() {
+            Some((spec, resampler)) => (*spec, resampler),
+            None => return Ok(false),
+        };
+        let samples = match resampler.process_block()? {
+            Some(samples) => samples,
+            None => return Ok(false),
+        };
+        self.push_samples(key, spec.channels, &samples, chunks)?;
+
+        Ok(true)
+    }
+
+    /// Convert the audio left in the resampler and drop it.
+    fn flush_resampler(
+        &mut self,
+        key: &ConversionKey,
+        chunks: &mut Vec<ConvertedChunk>,
+    ) -> anyhow::Result<()> {
+        if let Some((spec, mut resampler)) = self.resampler.take() {
+            resampler.finish();
+            while let Some(samples) = resampler.process_block()? {
+                self.push_samples(key, spec.channels, &samples, chunks)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mix interleaved source samples onto the device channels.
+    fn push_samples(
+        &mut self,
+        key: &ConversionKey,
+        source_channels: Channels,
+        samples: &[f32],
+        chunks: &mut Vec<ConvertedChunk>,
+    ) -> anyhow::Result<()> {
+        let is_current = self
+            .mix_matrix
+            .as_ref()
+            .is_some_and(|(channels, _)| *channels == source_channels);
+        if !is_current {
+            let mix_matrix = make_mix_matrix(key, source_channels)?;
+            self.mix_matrix = Some((source_channels, mix_matrix));
+        }
+        let (_, mix_matrix) = self.mix_matrix.as_ref().expect("missing mix matrix");
+
+        let mut converted = Vec::new();
+        mix_matrix.mix(samples, &mut converted);
+
+        if !converted.is_empty() {
+            chunks.push(ConvertedChunk {
+                seek_generation: self.seek_generation,
+                samples: Arc::new(converted),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the matrix that mixes a source layout onto the device channels.
+///
+/// # Errors
+/// Returns an error if a custom matrix does not fit the source or the device.
+fn make_mix_matrix(
+    key: &ConversionKey,
+    source_channels: Channels,
+) -> anyhow::Result<Arc<MixMatrix>> {
+    let mix_matrix = match key.mix_matrix.as_ref() {
+        Some(mix_matrix) => mix_matrix,
+        None => {
+            return Ok(Arc::new(MixMatrix::standard(
+                source_channels,
+                key.channel_layout,
+                key.num_channels,
+            )))
+        }
+    };
+
+    if mix_matrix.num_source_channels() != source_channels.count() {
+        anyhow::bail!(
+            "the mix matrix has gains for {} input channels, but the input has {}",
+            mix_matrix.num_source_channels(),
+            source_channels.count()
+        );
+    }
+    if mix_matrix.num_device_channels() != key.num_channels {
+        anyhow::bail!(
+            "the mix matrix has rows for {} device channels, but the device has {}",
+            mix_matrix.num_device_channels(),
+            key.num_channels
+        );
+    }
+
+    Ok(mix_matrix.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use symphonia::core::audio::Channels;
+
+    fn make_chunk(rate: u32, samples: Vec<f32>) -> Arc<AudioChunk> {
+        Arc::new(AudioChunk {
+            spec: SignalSpec::new(rate, Channels::FRONT_LEFT),
+            samples,
+        })
+    }
+
+    #[test]
+    fn shared_by_format() {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(8);
+        let seek_generation = Arc::new(AtomicU64::new(0));
+        let cache = ConversionCache::default();
+        let key = ConversionKey {
+            sample_rate: 48000,
+            num_channels: 2,
+            channel_layout: Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+            quality: ResampleQuality::SincBest,
+            mix_matrix: None,
+        };
+
+        let conversion = cache.get_or_insert_with(key.clone(), || {
+            Conversion::new(receiver, seek_generation.clone(), key.clone())
+        });
+        let shared =
+            cache.get_or_insert_with(key.clone(), || unreachable!("conversion made twice"));
+        assert!(Arc::ptr_eq(&conversion, &shared));
+
+        for i in 0..3 {
+            sender
+                .send((0, make_chunk(48000, vec![i as f32; 4])))
+                .expect("failed to send");
+        }
+        drop(sender);
+
+        let first = conversion.add_consumer();
+        let second = conversion.add_consumer();
+        for i in 0..3 {
+            let first_chunk = conversion.next_chunk(first).expect("failed to convert");
+            let first_chunk = first_chunk.expect("missing chunk");
+            assert_eq!(*first_chunk.samples, [i as f32; 8]);
+        }
+        assert!(conversion
+            .next_chunk(first)
+            .expect("failed to convert")
+            .is_none());
+
+        for _ in 0..3 {
+            let second_chunk = conversion.next_chunk(second).expect("failed to convert");
+            assert!(second_chunk.is_some());
+        }
+        assert!(conversion
+            .next_chunk(second)
+            .expect("failed to convert")
+            .is_none());
+
+        drop(conversion);
+        drop(shared);
+        let (_, receiver) = std::sync::mpsc::sync_channel(1);
+        let mut is_new = false;
+        cache.get_or_insert_with(key.clone(), || {
+            is_new = true;
+            Conversion::new(receiver, seek_generation, key)
+        });
+        assert!(is_new);
+    }
+
+    #[test]
+    fn lagging_consumers_read_while_converting() {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(8);
+        let key = ConversionKey {
+            sample_rate: 48000,
+            num_channels: 1,
+            channel_layout: Channels::FRONT_CENTRE,
+            quality: ResampleQuality::SincBest,
+            mix_matrix: None,
+        };
+        let conversion = Arc::new(Conversion::new(receiver, Arc::new(AtomicU64::new(0)), key));
+        let leading = conversion.add_consumer();
+        let lagging = conversion.add_consumer();
+
+        sender
+            .send((0, make_chunk(48000, vec![0.0; 4])))
+            .expect("failed to send");
+        let chunk = conversion.next_chunk(leading).expect("failed to convert");
+        assert!(chunk.is_some());
+
+        // The leading consumer waits on the decoder for the next chunk...
+        let leading_handle = {
+            let conversion = conversion.clone();
+            std::thread::spawn(move || conversion.next_chunk(leading))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+
+        // ...while the lagging one reads the chunk that is already converted.
+        let (lagging_sender, lagging_receiver) = std::sync::mpsc::channel();
+        {
+            let conversion = conversion.clone();
+            std::thread::spawn(move || {
+                let _ = lagging_sender.send(conversion.next_chunk(lagging));
+            });
+        }
+        let chunk = lagging_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the lagging consumer was blocked")
+            .expect("failed to convert");
+        assert_eq!(*chunk.expect("missing chunk").samples, [0.0; 4]);
+
+        sender
+            .send((0, make_chunk(48000, vec![1.0; 4])))
+            .expect("failed to send");
+        let chunk = leading_handle
+            .join()
+            .expect("the leading consumer panicked")
+            .expect("failed to convert");
+        assert_eq!(*chunk.expect("missing chunk").samples, [1.0; 4]);
+    }
+
+    #[test]
+    fn skips_stale_chunks() {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(8);
+        let seek_generation = Arc::new(AtomicU64::new(0));
+        let key = ConversionKey {
+            sample_rate: 44100,
+            num_channels: 1,
+            channel_layout: Channels::FRONT_CENTRE,
+            quality: ResampleQuality::SincBest,
+            mix_matrix: None,
+        };
+        let conversion = Conversion::new(receiver, seek_generation.clone(), key);
+        let consumer = conversion.add_consumer();
+
+        sender
+            .send((0, make_chunk(44100, vec![0.0; 4])))
+            .expect("failed to send");
+        seek_generation.store(1, Ordering::SeqCst);
+        sender
+            .send((1, make_chunk(44100, vec![1.0; 4])))
+            .expect("failed to send");
+        drop(sender);
+
+        let chunk = conversion.next_chunk(consumer).expect("failed to convert");
+        let chunk = chunk.expect("missing chunk");
+        assert_eq!(chunk.seek_generation, 1);
+        assert_eq!(*chunk.samples, [1.0; 4]);
+        assert!(conversion
+            .next_chunk(consumer)
+            .expect("failed to convert")
+            .is_none());
+    }
+}
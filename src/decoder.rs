@@ -0,0 +1,710 @@
+use crate::raw::RawPcmReader;
+use crate::raw::RawPcmSpec;
+use crate::sample::IntoF32Sample;
+use crate::track_info::TrackInfo;
+use anyhow::Context;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::Duration;
+use symphonia::core::audio::AudioBuffer;
+use symphonia::core::audio::AudioBufferRef;
+use symphonia::core::audio::Signal;
+use symphonia::core::audio::SignalSpec;
+use symphonia::core::codecs::CodecParameters;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::formats::FormatReader;
+use symphonia::core::formats::Packet;
+use symphonia::core::formats::SeekMode;
+use symphonia::core::formats::SeekTo;
+use symphonia::core::formats::Track;
+use symphonia::core::io::MediaSource;
+use symphonia::core::meta::Tag;
+use symphonia::core::probe::Hint;
+use symphonia::core::sample::Sample;
+use symphonia::core::units::TimeBase;
+
+/// A chunk of decoded audio
+#[derive(Debug, Clone)]
+pub struct AudioChunk {
+    /// The spec of the samples
+    pub spec: SignalSpec,
+
+    /// The interleaved f32 samples
+    pub samples: Vec<f32>,
+}
+
+impl AudioChunk {
+    /// Get the number of frames in this chunk
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.spec.channels.count()
+    }
+
+    /// Remove frames from the start of this chunk.
+    fn skip_frames(&mut self, frames: usize) {
+        self.samples.drain(..frames * self.spec.channels.count());
+    }
+
+    /// Shorten this chunk to the given number of frames.
+    fn truncate_frames(&mut self, frames: usize) {
+        self.samples.truncate(frames * self.spec.channels.count());
+    }
+}
+
+/// What to do when a packet fails to decode
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecodeErrorPolicy {
+    /// Replace the packet with silence of the same length
+    #[default]
+    Skip,
+
+    /// Stop decoding and return the error
+    Abort,
+}
+
+impl FromStr for DecodeErrorPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "skip" => Ok(Self::Skip),
+            "abort" => Ok(Self::Abort),
+            _ => anyhow::bail!("invalid decode error policy '{}'", input),
+        }
+    }
+}
+
+/// How to pick the track to decode
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub enum TrackSelector {
+    /// The container's default track
+    #[default]
+    Default,
+
+    /// The track at an index, in container order
+    Index(usize),
+
+    /// The first track in a language
+    Language(String),
+
+    /// The first track with a codec, by its short name
+    Codec(String),
+}
+
+impl TrackSelector {
+    /// Pick a track from a format reader.
+    ///
+    /// # Errors
+    /// Returns an error if no track matches.
+    fn select<'a>(&self, format: &'a dyn FormatReader) -> anyhow::Result<&'a Track> {
+        let tracks = format.tracks();
+        match self {
+            Self::Default => format.default_track().context("missing default track"),
+            Self::Index(index) => tracks.get(*index).with_context(|| {
+                format!(
+                    "missing track {}, there are only {} tracks",
+                    index,
+                    tracks.len()
+                )
+            }),
+            Self::Language(language) => tracks
+                .iter()
+                .find(|track| {
+                    track
+                        .language
+                        .as_deref()
+                        .is_some_and(|track_language| track_language.eq_ignore_ascii_case(language))
+                })
+                .with_context(|| format!("missing track in language '{}'", language)),
+            Self::Codec(codec) => tracks
+                .iter()
+                .find(|track| {
+                    symphonia::default::get_codecs()
+                        .get_codec(track.codec_params.codec)
+                        .is_some_and(|descriptor| descriptor.short_name.eq_ignore_ascii_case(codec))
+                })
+                .with_context(|| format!("missing track with codec '{}'", codec)),
+        }
+    }
+}
+
+impl FromStr for TrackSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if input == "default" {
+            return Ok(Self::Default);
+        }
+
+        if let Some(language) = input.strip_prefix("language:") {
+            return Ok(Self::Language(language.into()));
+        }
+
+        if let Some(codec) = input.strip_prefix("codec:") {
+            return Ok(Self::Codec(codec.into()));
+        }
+
+        match input.parse() {
+            Ok(index) => Ok(Self::Index(index)),
+            Err(_) => anyhow::bail!(
+                "invalid track '{}', expected 'default', an index, 'language:<language>' or 'codec:<codec>'",
+                input
+            ),
+        }
+    }
+}
+
+/// Options for a [`Decoder`]
+#[derive(Debug, Default, Clone)]
+pub struct DecodeOptions {
+    /// What to do when a packet fails to decode
+    pub error_policy: DecodeErrorPolicy,
+
+    /// The track to decode
+    pub track: TrackSelector,
+}
+
+/// Probe a media source and get information about all of its tracks.
+///
+/// The hint is used to guide format probing.
+pub fn probe_tracks(
+    media_source: Box<dyn MediaSource>,
+    hint: &Hint,
+) -> anyhow::Result<Vec<TrackInfo>> {
+    let (format, tags) = probe(media_source, hint)?;
+
+    Ok(format
+        .tracks()
+        .iter()
+        .map(|track| TrackInfo::new(track, &tags))
+        .collect())
+}
+
+/// Probe a media source, returning its format reader and tags.
+///
+/// Tags found while probing, like ID3v2, come before the container's own tags.
+fn probe(
+    media_source: Box<dyn MediaSource>,
+    hint: &Hint,
+) -> anyhow::Result<(Box<dyn FormatReader>, Vec<Tag>)> {
+    let media_source =
+        symphonia::core::io::MediaSourceStream::new(media_source, Default::default());
+
+    let format_options = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+
+    let mut probed = symphonia::default::get_probe()
+        .format(hint, media_source, &format_options, &Default::default())
+        .context("failed to probe")?;
+
+    let mut tags = Vec::new();
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        tags.extend(revision.tags().iter().cloned());
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.extend(revision.tags().iter().cloned());
+    }
+
+    Ok((probed.format, tags))
+}
+
+/// An incremental decoder for one track of a media source
+pub struct Decoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn symphonia::core::codecs::Decoder>,
+    track_id: u32,
+    options: DecodeOptions,
+    track_info: TrackInfo,
+
+    spec: Option<SignalSpec>,
+    num_skipped_packets: u64,
+
+    position: u64,
+    skip_frames: u64,
+    end: Option<Duration>,
+}
+
+impl Decoder {
+    /// Probe a media source and make a decoder for the track picked by the options.
+    ///
+    /// The hint is used to guide format probing.
+    /// Encoder delay and padding are trimmed from the decoded audio,
+    /// so that the inputs can be looped without gaps.
+    pub fn new(
+        media_source: Box<dyn MediaSource>,
+        hint: &Hint,
+        options: DecodeOptions,
+    ) -> anyhow::Result<Self> {
+        let (format, tags) = probe(media_source, hint)?;
+        Self::from_format(format, &tags, options)
+    }
+
+    /// Make a decoder for raw, headerless PCM with the given spec.
+    ///
+    /// The media source does not need to be seekable.
+    pub fn new_raw(
+        media_source: Box<dyn MediaSource>,
+        spec: &RawPcmSpec,
+        options: DecodeOptions,
+    ) -> anyhow::Result<Self> {
+        let media_source =
+            symphonia::core::io::MediaSourceStream::new(media_source, Default::default());
+        let format = RawPcmReader::new(media_source, spec)?;
+        Self::from_format(Box::new(format), &[], options)
+    }
+
+    /// Make a decoder for the track picked by the options from a format reader.
+    fn from_format(
+        format: Box<dyn FormatReader>,
+        tags: &[Tag],
+        options: DecodeOptions,
+    ) -> anyhow::Result<Self> {
+        let track = options.track.select(format.as_ref())?;
+        let track_id = track.id;
+        let track_info = TrackInfo::new(track, tags);
+
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &Default::default())
+            .context("failed to make decoder")?;
+
+        Ok(Self {
+            format,
+            decoder,
+            track_id,
+            options,
+            track_info,
+
+            spec: None,
+            num_skipped_packets: 0,
+
+            position: 0,
+            skip_frames: 0,
+            end: None,
+        })
+    }
+
+    /// Get information about the track being decoded.
+    pub fn track_info(&self) -> &TrackInfo {
+        &self.track_info
+    }
+
+    /// Get the number of packets that failed to decode and were replaced with silence.
+    pub fn num_skipped_packets(&self) -> u64 {
+        self.num_skipped_packets
+    }
+
+    /// Get the number of frames from the start of the track to the start of the next chunk.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Set where the stream ends.
+    ///
+    /// `None` plays until the end of the track.
+    pub fn set_end(&mut self, end: Option<Duration>) {
+        self.end = end;
+    }
+
+    /// Seek to a time from the start of the track.
+    ///
+    /// The next chunk starts exactly at the given time.
+    ///
+    /// # Errors
+    /// Returns an error if the media source could not seek there.
+    pub fn seek(&mut self, time: Duration) -> anyhow::Result<()> {
+        let seeked_to = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: time.into(),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .context("failed to seek")?;
+        self.decoder.reset();
+
+        let rate = self
+            .spec
+            .map(|spec| spec.rate)
+            .or(self.decoder.codec_params().sample_rate)
+            .context("unknown sample rate")?;
+        let required_frames = self.ts_to_frames(seeked_to.required_ts, rate);
+        let actual_frames = self.ts_to_frames(seeked_to.actual_ts, rate);
+
+        self.position = required_frames;
+        self.skip_frames = required_frames.saturating_sub(actual_frames);
+
+        Ok(())
+    }
+
+    /// Decode the next chunk.
+    ///
+    /// Returns `None` at the end of the stream.
+    pub fn next_chunk(&mut self) -> anyhow::Result<Option<AudioChunk>> {
+        loop {
+            let mut chunk = match self.decode_next_packet()? {
+                Some(chunk) => chunk,
+                None => return Ok(None),
+            };
+
+            // Drop the frames between the seeked packet and the seek target.
+            let skip_frames = usize::try_from(self.skip_frames)
+                .unwrap_or(usize::MAX)
+                .min(chunk.frames());
+            chunk.skip_frames(skip_frames);
+            self.skip_frames -= skip_frames as u64;
+
+            if let Some(end) = self.end {
+                let end = duration_to_frames(end, chunk.spec.rate);
+                let remaining_frames = end.saturating_sub(self.position);
+                if remaining_frames == 0 {
+                    return Ok(None);
+                }
+
+                let remaining_frames = usize::try_from(remaining_frames).unwrap_or(usize::MAX);
+                chunk.truncate_frames(remaining_frames.min(chunk.frames()));
+            }
+
+            if chunk.frames() == 0 {
+                continue;
+            }
+
+            self.position += chunk.frames() as u64;
+
+            return Ok(Some(chunk));
+        }
+    }
+
+    /// Decode the next packet of the track.
+    ///
+    /// Returns `None` at the end of the stream.
+    fn decode_next_packet(&mut self) -> anyhow::Result<Option<AudioChunk>> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(symphonia::core::errors::Error::IoError(e)) => {
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                        return Ok(None);
+                    } else {
+                        anyhow::bail!("Failed to get next packet: {:?}", e);
+                    }
+                }
+                Err(e) => {
+                    anyhow::bail!("Failed to get next packet: {:?}", e);
+                }
+            };
+
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(_)) | Err(SymphoniaError::IoError(_))
+                    if self.options.error_policy == DecodeErrorPolicy::Skip =>
+                {
+                    self.num_skipped_packets += 1;
+                    match self.make_silence(&packet) {
+                        Some(chunk) => return Ok(Some(chunk)),
+                        None => continue,
+                    }
+                }
+                Err(e) => {
+                    return Err(e).context("packet decode failed");
+                }
+            };
+            if decoded.frames() == 0 {
+                continue;
+            }
+
+            let spec = *decoded.spec();
+            self.spec = Some(spec);
+            let mut samples = Vec::with_capacity(decoded.frames() * spec.channels.count());
+            match decoded {
+                AudioBufferRef::U8(decoded) => append_audio_buffer(&decoded, &mut samples),
+                AudioBufferRef::U16(decoded) => append_audio_buffer(&decoded, &mut samples),
+                AudioBufferRef::U24(decoded) => append_audio_buffer(&decoded, &mut samples),
+                AudioBufferRef::U32(decoded) => append_audio_buffer(&decoded, &mut samples),
+                AudioBufferRef::S8(decoded) => append_audio_buffer(&decoded, &mut samples),
+                AudioBufferRef::S16(decoded) => append_audio_buffer(&decoded, &mut samples),
+                AudioBufferRef::S24(decoded) => append_audio_buffer(&decoded, &mut samples),
+                AudioBufferRef::S32(decoded) => append_audio_buffer(&decoded, &mut samples),
+                AudioBufferRef::F32(decoded) => append_audio_buffer(&decoded, &mut samples),
+                AudioBufferRef::F64(decoded) => append_audio_buffer(&decoded, &mut samples),
+            }
+
+            return Ok(Some(AudioChunk { spec, samples }));
+        }
+    }
+
+    /// Make a chunk of silence as long as the given packet.
+    ///
+    /// Returns `None` if the spec is not yet known or the packet is empty.
+    fn make_silence(&self, packet: &Packet) -> Option<AudioChunk> {
+        let codec_params = self.decoder.codec_params();
+        let spec = self.spec.or_else(|| get_codec_params_spec(codec_params))?;
+
+        let frames = usize::try_from(self.ts_to_frames(packet.dur, spec.rate)).ok()?;
+        if frames == 0 {
+            return None;
+        }
+
+        Some(AudioChunk {
+            spec,
+            samples: vec![0.0; frames * spec.channels.count()],
+        })
+    }
+
+    /// Convert a timestamp or duration in the track's time base to frames at the given rate.
+    fn ts_to_frames(&self, ts: u64, rate: u32) -> u64 {
+        ts_to_frames(self.decoder.codec_params().time_base, ts, rate)
+    }
+}
+
+/// Convert a timestamp or duration in a time base to frames at the given rate.
+///
+/// Without a time base, the timestamp is assumed to already be in frames.
+pub(crate) fn ts_to_frames(time_base: Option<TimeBase>, ts: u64, rate: u32) -> u64 {
+    match time_base {
+        Some(time_base) => {
+            let frames = u128::from(ts) * u128::from(time_base.numer) * u128::from(rate)
+                / u128::from(time_base.denom);
+            u64::try_from(frames).unwrap_or(u64::MAX)
+        }
+        None => ts,
+    }
+}
+
+/// Convert a duration to frames at the given rate.
+fn duration_to_frames(duration: Duration, rate: u32) -> u64 {
+    let frames = duration.as_nanos() * u128::from(rate) / 1_000_000_000;
+    u64::try_from(frames).unwrap_or(u64::MAX)
+}
+
+/// Get the signal spec from codec parameters, if they are complete.
+fn get_codec_params_spec(codec_params: &CodecParameters) -> Option<SignalSpec> {
+    Some(SignalSpec::new(
+        codec_params.sample_rate?,
+        codec_params.channels?,
+    ))
+}
+
+/// Convert a decoded audio buffer to f32 samples and append them to the interleaved buffer.
+///
+/// All channels of the decoded buffer are interleaved, in the order of its channel layout.
+fn append_audio_buffer<S>(decoded: &AudioBuffer<S>, samples: &mut Vec<f32>)
+where
+    S: Sample + IntoF32Sample,
+{
+    let planes = decoded.planes();
+    let planes = planes.planes();
+
+    samples.reserve(decoded.frames() * planes.len());
+    for frame in 0..decoded.frames() {
+        samples.extend(planes.iter().map(|plane| plane[frame].into_f32_sample()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The size of a 128 kbps, 44.1 kHz, mono MPEG-1 Layer 3 frame.
+    const MP3_FRAME_LEN: usize = 417;
+
+    /// The number of audio frames in an MPEG-1 Layer 3 frame.
+    const MP3_FRAME_DURATION: usize = 1152;
+
+    /// The encoder delay stored in synthesized LAME tags.
+    const ENC_DELAY: u32 = 576;
+
+    /// The encoder padding stored in synthesized LAME tags.
+    const ENC_PADDING: u32 = 1000;
+
+    /// Synthesize a silent mp3 with a Xing/LAME tag.
+    ///
+    /// The audio frames at the corrupt indices get invalid side information.
+    fn synthesize_mp3(num_frames: u32, corrupt_frames: &[u32]) -> Vec<u8> {
+        const HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0xC0];
+        const SIDE_INFO_LEN: usize = 17;
+
+        let mut mp3 = Vec::new();
+
+        // The info frame.
+        let mut frame = Vec::with_capacity(MP3_FRAME_LEN);
+        frame.extend(HEADER);
+        frame.extend([0; SIDE_INFO_LEN]);
+        frame.extend(b"Info");
+        frame.extend(1_u32.to_be_bytes());
+        frame.extend(num_frames.to_be_bytes());
+        frame.extend(b"Lavf58.29");
+        frame.extend([0; 12]);
+        frame.extend(&((ENC_DELAY << 12) | ENC_PADDING).to_be_bytes()[1..]);
+        frame.resize(MP3_FRAME_LEN, 0);
+        mp3.extend(frame);
+
+        // The silent audio frames.
+        for i in 0..num_frames {
+            let mut frame = Vec::with_capacity(MP3_FRAME_LEN);
+            frame.extend(HEADER);
+            if corrupt_frames.contains(&i) {
+                // This makes big_values too large.
+                frame.extend([0xFF; SIDE_INFO_LEN]);
+            }
+            frame.resize(MP3_FRAME_LEN, 0);
+            mp3.extend(frame);
+        }
+
+        mp3
+    }
+
+    fn make_mp3_decoder(mp3: Vec<u8>, options: DecodeOptions) -> Decoder {
+        let mut hint = Hint::new();
+        hint.with_extension("mp3");
+        Decoder::new(Box::new(std::io::Cursor::new(mp3)), &hint, options).expect("failed to probe")
+    }
+
+    /// Get the number of frames left in a synthesized mp3 after trimming.
+    fn get_expected_frames(num_frames: u32) -> usize {
+        // The LAME tag stores both values relative to the 529 sample decoder delay,
+        // so the total trim is exactly the sum of the stored values.
+        num_frames as usize * MP3_FRAME_DURATION - (ENC_DELAY + ENC_PADDING) as usize
+    }
+
+    #[test]
+    fn gapless_trims_delay_and_padding() {
+        let num_frames = 10;
+        let mp3 = synthesize_mp3(num_frames, &[]);
+        let mut decoder = make_mp3_decoder(mp3, DecodeOptions::default());
+
+        let mut decoded_frames = 0;
+        while let Some(chunk) = decoder.next_chunk().expect("failed to decode") {
+            assert_eq!(chunk.spec.rate, 44_100);
+            assert_eq!(chunk.spec.channels.count(), 1);
+            decoded_frames += chunk.frames();
+        }
+
+        assert_eq!(decoded_frames, get_expected_frames(num_frames));
+    }
+
+    #[test]
+    fn track_info_has_exact_duration() {
+        let num_frames = 10;
+        let mp3 = synthesize_mp3(num_frames, &[]);
+        let decoder = make_mp3_decoder(mp3, DecodeOptions::default());
+
+        let track_info = decoder.track_info();
+        assert_eq!(
+            track_info.num_frames,
+            Some(get_expected_frames(num_frames) as u64)
+        );
+        assert_eq!(track_info.codec_params.sample_rate, Some(44100));
+    }
+
+    #[test]
+    fn seek_is_frame_accurate() {
+        let num_frames = 10;
+        let mp3 = synthesize_mp3(num_frames, &[]);
+        let mut decoder = make_mp3_decoder(mp3, DecodeOptions::default());
+
+        decoder
+            .seek(Duration::from_millis(100))
+            .expect("failed to seek");
+        assert_eq!(decoder.position(), 4410);
+
+        let mut decoded_frames = 0;
+        while let Some(chunk) = decoder.next_chunk().expect("failed to decode") {
+            decoded_frames += chunk.frames();
+        }
+
+        assert_eq!(decoded_frames, get_expected_frames(num_frames) - 4410);
+        assert_eq!(decoder.position(), get_expected_frames(num_frames) as u64);
+    }
+
+    #[test]
+    fn end_is_frame_accurate() {
+        let mp3 = synthesize_mp3(10, &[]);
+        let mut decoder = make_mp3_decoder(mp3, DecodeOptions::default());
+
+        decoder
+            .seek(Duration::from_millis(20))
+            .expect("failed to seek");
+        decoder.set_end(Some(Duration::from_millis(150)));
+
+        let mut decoded_frames = 0;
+        while let Some(chunk) = decoder.next_chunk().expect("failed to decode") {
+            decoded_frames += chunk.frames();
+        }
+
+        assert_eq!(decoded_frames, 6615 - 882);
+    }
+
+    #[test]
+    fn select_track() {
+        let mp3 = synthesize_mp3(10, &[]);
+
+        for track in ["default", "0", "codec:MP3"].iter() {
+            let options = DecodeOptions {
+                track: track.parse().expect("invalid track selector"),
+                ..Default::default()
+            };
+            let media_source = Box::new(std::io::Cursor::new(mp3.clone()));
+            assert!(Decoder::new(media_source, &Hint::new(), options).is_ok());
+        }
+
+        for track in ["1", "codec:flac", "language:eng"].iter() {
+            let options = DecodeOptions {
+                track: track.parse().expect("invalid track selector"),
+                ..Default::default()
+            };
+            let media_source = Box::new(std::io::Cursor::new(mp3.clone()));
+            assert!(Decoder::new(media_source, &Hint::new(), options).is_err());
+        }
+
+        assert!("first".parse::<TrackSelector>().is_err());
+    }
+
+    #[test]
+    fn skip_corrupt_packets() {
+        let num_frames = 10;
+        let mp3 = synthesize_mp3(num_frames, &[3, 7]);
+        let mut decoder = make_mp3_decoder(
+            mp3,
+            DecodeOptions {
+                error_policy: DecodeErrorPolicy::Skip,
+                ..Default::default()
+            },
+        );
+
+        let mut decoded_frames = 0;
+        while let Some(chunk) = decoder.next_chunk().expect("failed to decode") {
+            decoded_frames += chunk.frames();
+        }
+
+        assert_eq!(decoder.num_skipped_packets(), 2);
+        assert_eq!(decoded_frames, get_expected_frames(num_frames));
+    }
+
+    #[test]
+    fn abort_on_corrupt_packets() {
+        let mp3 = synthesize_mp3(10, &[3]);
+        let mut decoder = make_mp3_decoder(
+            mp3,
+            DecodeOptions {
+                error_policy: DecodeErrorPolicy::Abort,
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..3 {
+            decoder
+                .next_chunk()
+                .expect("failed to decode")
+                .expect("missing chunk");
+        }
+        assert!(decoder.next_chunk().is_err());
+    }
+}
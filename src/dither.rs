@@ -0,0 +1,241 @@
+use anyhow::Context;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// How to dither audio when converting it to integer samples
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DitherMode {
+    /// Round to the nearest integer
+    None,
+
+    /// Add triangular dither of 1 LSB either way before rounding
+    #[default]
+    Tpdf,
+
+    /// Add triangular dither,
+    /// and shape the noise away from the low frequencies that ears are most sensitive to
+    Shaped,
+}
+
+impl DitherMode {
+    /// All dither modes
+    pub const ALL: &'static [Self] = &[Self::None, Self::Tpdf, Self::Shaped];
+
+    /// Get the name of this mode, as used on the command line and in config files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Tpdf => "tpdf",
+            Self::Shaped => "shaped",
+        }
+    }
+}
+
+impl FromStr for DitherMode {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|mode| mode.as_str() == input)
+            .with_context(|| format!("invalid dither mode '{}'", input))
+    }
+}
+
+impl fmt::Display for DitherMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// A small, seedable random number generator (xorshift64*).
+///
+/// Dither only needs noise that is uncorrelated with the audio, so this is not cryptographic.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Make a new [`Rng`], which always gives the same numbers for the same seed.
+    pub fn new(seed: u64) -> Self {
+        // The state must never be zero. Scramble the seed so that nearby seeds diverge quickly.
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+        Self(if state == 0 { 1 } else { state })
+    }
+
+    /// Get the next random 64 bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Get a random number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+/// Dithers and rounds samples that were scaled to integer steps.
+///
+/// Each channel keeps its own noise shaping state, so channels must be passed in their own order.
+#[derive(Debug, Clone)]
+pub struct Dither {
+    mode: DitherMode,
+    rng: Rng,
+
+    /// The last two rounding errors of each channel, for noise shaping
+    errors: Vec<[f64; 2]>,
+}
+
+impl Dither {
+    /// Make a new [`Dither`], with a seed for its noise.
+    pub fn new(mode: DitherMode, seed: u64) -> Self {
+        Self {
+            mode,
+            rng: Rng::new(seed),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Get the dither mode.
+    pub fn mode(&self) -> DitherMode {
+        self.mode
+    }
+
+    /// Round a sample on a channel, scaled so that 1.0 is one integer step.
+    ///
+    /// The result is not clipped.
+    pub fn quantize(&mut self, channel: usize, sample: f64) -> f64 {
+        match self.mode {
+            DitherMode::None => sample.round(),
+            DitherMode::Tpdf => (sample + self.tpdf()).round(),
+            DitherMode::Shaped => {
+                if channel >= self.errors.len() {
+                    self.errors.resize(channel + 1, [0.0; 2]);
+                }
+
+                // Feeding back the last errors with 2, -1 shapes the noise by (1 - z^-1)^2,
+                // which moves it from the low frequencies up towards Nyquist.
+                let [last_error, second_last_error] = self.errors[channel];
+                let wanted = sample - 2.0 * last_error + second_last_error;
+                let quantized = (wanted + self.tpdf()).round();
+
+                // Clipped samples would feed back errors the output can never make up for,
+                // so they are limited to keep the loop stable.
+                let error = (quantized - wanted).clamp(-2.0, 2.0);
+                self.errors[channel] = [error, last_error];
+
+                quantized
+            }
+        }
+    }
+
+    /// Get triangular noise from -1 to 1.
+    fn tpdf(&mut self) -> f64 {
+        self.rng.next_f64() - self.rng.next_f64()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_mode() {
+        for mode in DitherMode::ALL.iter() {
+            assert_eq!(mode.as_str().parse::<DitherMode>().unwrap(), *mode);
+        }
+        assert!("triangular".parse::<DitherMode>().is_err());
+    }
+
+    #[test]
+    fn rng_is_seedable() {
+        let numbers = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..16).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+        assert_eq!(numbers(7), numbers(7));
+        assert_ne!(numbers(7), numbers(8));
+
+        let mut rng = Rng::new(0);
+        for _ in 0..1000 {
+            let number = rng.next_f64();
+            assert!((0.0..1.0).contains(&number));
+        }
+    }
+
+    /// A sine well below one step, like the tail of a fade out
+    fn quiet_sine(len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|i| 0.4 * (i as f64 * 2.0 * std::f64::consts::PI / 48.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn dither_keeps_quiet_signals() {
+        let input = quiet_sine(48000);
+        let correlation = |mode| {
+            let mut dither = Dither::new(mode, 1);
+            input
+                .iter()
+                .map(|sample| sample * dither.quantize(0, *sample))
+                .sum::<f64>()
+                / input.len() as f64
+        };
+
+        // Rounding alone loses the sine completely. Dither keeps it, under the noise.
+        assert_eq!(correlation(DitherMode::None), 0.0);
+        for mode in [DitherMode::Tpdf, DitherMode::Shaped].iter() {
+            let correlation = correlation(*mode);
+            assert!(
+                (correlation - 0.08).abs() < 0.01,
+                "{}: {}",
+                mode,
+                correlation
+            );
+        }
+    }
+
+    #[test]
+    fn dither_is_deterministic() {
+        let input = quiet_sine(1000);
+        let output = |seed| {
+            let mut dither = Dither::new(DitherMode::Shaped, seed);
+            input
+                .iter()
+                .enumerate()
+                .map(|(i, sample)| dither.quantize(i % 2, *sample))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(output(3), output(3));
+        assert_ne!(output(3), output(4));
+    }
+
+    #[test]
+    fn noise_shaping_moves_noise_out_of_the_low_frequencies() {
+        // Summing blocks of the error is a crude low-pass filter.
+        let input = quiet_sine(48000);
+        let low_frequency_noise = |mode| {
+            let mut dither = Dither::new(mode, 2);
+            let errors: Vec<f64> = input
+                .iter()
+                .map(|sample| dither.quantize(0, *sample) - sample)
+                .collect();
+            errors
+                .chunks(48)
+                .map(|block| block.iter().sum::<f64>().powi(2))
+                .sum::<f64>()
+        };
+
+        let tpdf = low_frequency_noise(DitherMode::Tpdf);
+        let shaped = low_frequency_noise(DitherMode::Shaped);
+        assert!(shaped * 10.0 < tpdf, "{} vs {}", shaped, tpdf);
+    }
+}
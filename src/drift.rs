@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How much history a device's rate is estimated from.
+///
+/// Longer windows average out more of the jitter in when device threads wake up.
+const ESTIMATION_WINDOW: Duration = Duration::from_secs(60);
+
+/// How often the device's rate is fit again
+const REFIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How much history is needed before a device's rate is trusted over its nominal rate
+const MIN_ESTIMATION_SPAN: Duration = Duration::from_secs(2);
+
+/// How fast playback is pulled back towards the wall clock, per second of drift, per second
+const PROPORTIONAL_GAIN: f64 = 0.05;
+
+/// The most a device's rate is corrected by, as a fraction of its nominal rate
+const MAX_CORRECTION: f64 = 0.002;
+
+/// An estimate of how fast a device actually consumes frames, by wall-clock time.
+///
+/// Device clocks are not exactly their nominal rates,
+/// so the rate is fit by least squares over the recent frames the device consumed.
+#[derive(Debug)]
+pub struct DriftEstimator {
+    nominal_rate: f64,
+
+    /// Recent (seconds since the start, frames consumed) measurements
+    measurements: VecDeque<(f64, f64)>,
+
+    /// The last fit rate ratio, and when it was fit
+    rate_ratio: f64,
+    last_fit_time: f64,
+}
+
+impl DriftEstimator {
+    /// Make a new [`DriftEstimator`] for a device with a nominal sample rate.
+    pub fn new(nominal_rate: u32) -> Self {
+        Self {
+            nominal_rate: f64::from(nominal_rate),
+            measurements: VecDeque::new(),
+
+            rate_ratio: 1.0,
+            last_fit_time: 0.0,
+        }
+    }
+
+    /// Record how many frames the device consumed since it started.
+    pub fn update(&mut self, elapsed: Duration, num_frames_consumed: u64) {
+        let time = elapsed.as_secs_f64();
+        self.measurements
+            .push_back((time, num_frames_consumed as f64));
+        while self
+            .measurements
+            .front()
+            .is_some_and(|(first_time, _)| time - first_time > ESTIMATION_WINDOW.as_secs_f64())
+        {
+            self.measurements.pop_front();
+        }
+
+        if time - self.last_fit_time >= REFIT_INTERVAL.as_secs_f64() {
+            if let Some(rate_ratio) = self.fit() {
+                self.rate_ratio = rate_ratio;
+                self.last_fit_time = time;
+            }
+        }
+    }
+
+    /// Get the device's rate relative to its nominal rate, like `1.0001` for 100 ppm fast.
+    ///
+    /// This is `1.0` until enough has been measured.
+    pub fn rate_ratio(&self) -> f64 {
+        self.rate_ratio
+    }
+
+    /// Fit the device's rate ratio to the measurements, if they span long enough.
+    fn fit(&self) -> Option<f64> {
+        let (first, last) = (self.measurements.front()?, self.measurements.back()?);
+        if last.0 - first.0 < MIN_ESTIMATION_SPAN.as_secs_f64() {
+            return None;
+        }
+        let (first_time, first_frames) = *first;
+
+        // Fit relative to the first measurement, to keep the sums small.
+        let num_measurements = self.measurements.len() as f64;
+        let (mut time_sum, mut frames_sum) = (0.0, 0.0);
+        for (time, frames) in self.measurements.iter() {
+            time_sum += time - first_time;
+            frames_sum += frames - first_frames;
+        }
+        let (time_mean, frames_mean) = (time_sum / num_measurements, frames_sum / num_measurements);
+
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for (time, frames) in self.measurements.iter() {
+            let time = time - first_time - time_mean;
+            let frames = frames - first_frames - frames_mean;
+            covariance += time * frames;
+            variance += time * time;
+        }
+
+        Some((covariance / variance) / self.nominal_rate)
+    }
+}
+
+/// Keeps a device's playback in step with the wall clock,
+/// so that devices playing the same audio do not drift apart.
+///
+/// The audio is resampled by the returned correction,
+/// which follows the estimated device rate and pulls any accumulated drift back to zero.
+#[derive(Debug)]
+pub struct DriftCompensator {
+    estimator: DriftEstimator,
+    nominal_rate: f64,
+
+    correction: f64,
+    last_num_frames_consumed: u64,
+
+    /// How many seconds of audio the device has played
+    played: f64,
+}
+
+impl DriftCompensator {
+    /// Make a new [`DriftCompensator`] for a device with a nominal sample rate.
+    pub fn new(nominal_rate: u32) -> Self {
+        Self {
+            estimator: DriftEstimator::new(nominal_rate),
+            nominal_rate: f64::from(nominal_rate),
+
+            correction: 1.0,
+            last_num_frames_consumed: 0,
+
+            played: 0.0,
+        }
+    }
+
+    /// Record how many frames the device consumed since it started, and get the new correction.
+    ///
+    /// The correction is the number of device frames to play for each frame of audio,
+    /// so it is above `1.0` when the device runs fast.
+    pub fn update(&mut self, elapsed: Duration, num_frames_consumed: u64) -> f64 {
+        // The frames consumed since the last update were resampled by the last correction.
+        let num_new_frames = num_frames_consumed.saturating_sub(self.last_num_frames_consumed);
+        self.last_num_frames_consumed = num_frames_consumed;
+        self.played += num_new_frames as f64 / self.nominal_rate / self.correction;
+
+        self.estimator.update(elapsed, num_frames_consumed);
+
+        let drift = self.drift(elapsed);
+        let correction = self.estimator.rate_ratio() * (1.0 + PROPORTIONAL_GAIN * drift);
+        self.correction = correction.clamp(1.0 - MAX_CORRECTION, 1.0 + MAX_CORRECTION);
+        self.correction
+    }
+
+    /// Get the current correction.
+    pub fn correction(&self) -> f64 {
+        self.correction
+    }
+
+    /// Get how far the audio the device has played is ahead of the wall clock.
+    ///
+    /// This is negative when the device is behind.
+    pub fn drift(&self, elapsed: Duration) -> f64 {
+        self.played - elapsed.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A device that consumes 10 ms periods on its own, skewed clock,
+    /// and signals its thread after each one like a WASAPI event
+    struct SimulatedSink {
+        rate: f64,
+        period: u64,
+        num_periods: u64,
+        jitter: u32,
+    }
+
+    impl SimulatedSink {
+        fn new(nominal_rate: u32, skew_ppm: f64) -> Self {
+            Self {
+                rate: f64::from(nominal_rate) * (1.0 + skew_ppm / 1_000_000.0),
+                period: u64::from(nominal_rate) / 100,
+                num_periods: 0,
+                jitter: 12345,
+            }
+        }
+
+        /// Wait for the next period to be consumed, and get when the thread woke up,
+        /// late by a varying amount, and how many frames the device consumed.
+        fn wait(&mut self) -> (Duration, u64) {
+            self.num_periods += 1;
+            let num_frames_consumed = self.num_periods * self.period;
+
+            self.jitter = self.jitter.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let late = Duration::from_micros(u64::from(self.jitter >> 16) % 3000);
+            let elapsed = Duration::from_secs_f64(num_frames_consumed as f64 / self.rate) + late;
+
+            (elapsed, num_frames_consumed)
+        }
+    }
+
+    #[test]
+    fn estimates_skewed_rates() {
+        for skew_ppm in [-250.0, -20.0, 0.0, 75.0, 400.0].iter() {
+            let mut sink = SimulatedSink::new(48000, *skew_ppm);
+            let mut estimator = DriftEstimator::new(48000);
+            for _ in 0..6000 {
+                let (elapsed, num_frames_consumed) = sink.wait();
+                estimator.update(elapsed, num_frames_consumed);
+            }
+
+            let estimated_ppm = (estimator.rate_ratio() - 1.0) * 1_000_000.0;
+            assert!(
+                (estimated_ppm - skew_ppm).abs() < 2.0,
+                "estimated {} ppm, expected {} ppm",
+                estimated_ppm,
+                skew_ppm
+            );
+        }
+    }
+
+    #[test]
+    fn drift_stays_bounded() {
+        // Uncorrected, the first two would drift apart by 270 ms over 10 minutes.
+        for (nominal_rate, skew_ppm) in [
+            (48000, 300.0),
+            (44100, -150.0),
+            (48000, 0.0),
+            (96000, -500.0),
+        ]
+        .iter()
+        {
+            let mut sink = SimulatedSink::new(*nominal_rate, *skew_ppm);
+            let mut compensator = DriftCompensator::new(*nominal_rate);
+            loop {
+                let (elapsed, num_frames_consumed) = sink.wait();
+                if elapsed > Duration::from_secs(600) {
+                    break;
+                }
+                compensator.update(elapsed, num_frames_consumed);
+
+                // Threads wake up to 3 ms late, so the drift is only known to within that.
+                let drift = compensator.drift(elapsed);
+                let bound = if elapsed < Duration::from_secs(120) {
+                    0.02
+                } else {
+                    0.002
+                };
+                assert!(
+                    drift.abs() < bound,
+                    "drifted {} ms after {:?} at {} ppm",
+                    drift * 1000.0,
+                    elapsed,
+                    skew_ppm
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn correction_is_limited() {
+        // A wildly skewed clock is corrected as much as allowed, and no more.
+        let mut sink = SimulatedSink::new(48000, 10_000.0);
+        let mut compensator = DriftCompensator::new(48000);
+        for _ in 0..6000 {
+            let (elapsed, num_frames_consumed) = sink.wait();
+            compensator.update(elapsed, num_frames_consumed);
+        }
+        assert_eq!(compensator.correction(), 1.0 + MAX_CORRECTION);
+    }
+}
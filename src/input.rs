@@ -0,0 +1,88 @@
+use crate::raw::RawPcmSpec;
+use std::path::PathBuf;
+use symphonia::core::io::MediaSource;
+use symphonia::core::io::ReadOnlySource;
+use symphonia::core::probe::Hint;
+
+const DONACDUM_MP3_BYTES: &[u8] =
+    include_bytes!("../assets/Payday 2 - DonAcDum EarRape-311954012.mp3");
+
+/// An audio input
+#[derive(Debug, Clone)]
+pub enum Input {
+    /// The embedded DonAcDum clip
+    Embedded,
+
+    /// A file on disk
+    File(PathBuf),
+
+    /// Standard input.
+    ///
+    /// It is probed like a file unless it is declared to be raw PCM.
+    Stdin { raw_pcm: Option<RawPcmSpec> },
+}
+
+impl Input {
+    /// Open this input as a media source.
+    ///
+    /// # Errors
+    /// Returns an error if the input could not be opened.
+    pub fn open(&self) -> std::io::Result<Box<dyn MediaSource>> {
+        match self {
+            Self::Embedded => Ok(Box::new(std::io::Cursor::new(DONACDUM_MP3_BYTES))),
+            Self::File(path) => Ok(Box::new(std::fs::File::open(path)?)),
+            Self::Stdin { .. } => Ok(Box::new(ReadOnlySource::new(std::io::stdin()))),
+        }
+    }
+
+    /// Returns `true` if this input can be opened and played more than once.
+    ///
+    /// Standard input can only be played once.
+    pub fn is_repeatable(&self) -> bool {
+        !matches!(self, Self::Stdin { .. })
+    }
+
+    /// Get the spec of this input if it is raw PCM.
+    pub fn raw_pcm_spec(&self) -> Option<&RawPcmSpec> {
+        match self {
+            Self::Stdin { raw_pcm } => raw_pcm.as_ref(),
+            Self::Embedded | Self::File(_) => None,
+        }
+    }
+
+    /// Make a probe hint for this input.
+    ///
+    /// The hint is derived from the file extension, if there is one, and the given MIME type.
+    pub fn hint(&self, mime_type: Option<&str>) -> Hint {
+        let mut hint = Hint::new();
+
+        match self {
+            Self::Embedded => {
+                hint.with_extension("mp3");
+                hint.mime_type("audio/mpeg");
+            }
+            Self::File(path) => {
+                if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+                    hint.with_extension(extension);
+                }
+            }
+            Self::Stdin { .. } => {}
+        }
+
+        if let Some(mime_type) = mime_type {
+            hint.mime_type(mime_type);
+        }
+
+        hint
+    }
+}
+
+impl std::fmt::Display for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Embedded => "<embedded>".fmt(f),
+            Self::File(path) => path.display().fmt(f),
+            Self::Stdin { .. } => "<stdin>".fmt(f),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! Decode audio files into a stream of f32 samples, ready to play on an audio device
+pub mod clock;
+pub mod config;
+pub mod convert;
+pub mod decoder;
+pub mod dither;
+pub mod drift;
+pub mod input;
+pub mod mix;
+pub mod null;
+pub mod pcm;
+pub mod pipe;
+pub mod playback;
+pub mod playlist;
+pub mod raw;
+pub mod resample;
+pub mod sample;
+pub mod simulated;
+pub mod sinc;
+pub mod sink;
+pub mod stream;
+pub mod timestamp;
+pub mod track_info;
+pub mod wav;
@@ -1,54 +1,259 @@
-///! https://gamedev.net/forums/topic/699061-implementing-flac-playback-through-wasapi/5391519/
-mod util;
+//! https://gamedev.net/forums/topic/699061-implementing-flac-playback-through-wasapi/5391519/
+#[cfg(windows)]
+mod wasapi;
 
-use self::util::decode_raw_audio_buffer;
+#[cfg(windows)]
+use crate::wasapi::WasapiBackend;
 use anyhow::Context;
-use std::convert::TryInto;
-use std::os::windows::raw::HANDLE;
+use argh::FromArgs;
+use donacdum::clock::SystemClock;
+use donacdum::config::Config;
+use donacdum::convert::ConversionKey;
+use donacdum::decoder::probe_tracks;
+use donacdum::decoder::DecodeErrorPolicy;
+use donacdum::decoder::DecodeOptions;
+use donacdum::decoder::TrackSelector;
+use donacdum::dither::Dither;
+use donacdum::dither::DitherMode;
+use donacdum::input::Input;
+use donacdum::mix::default_layout;
+use donacdum::mix::MixMatrix;
+use donacdum::pcm::PcmFormat;
+use donacdum::pipe::PipeSink;
+use donacdum::playback::play;
+use donacdum::playback::PlaybackOptions;
+use donacdum::playlist::load_inputs;
+use donacdum::raw::RawPcmSpec;
+use donacdum::raw::RawSampleFormat;
+use donacdum::resample::measure_quality;
+use donacdum::resample::ResampleQuality;
+use donacdum::simulated::SimulatedBackend;
+use donacdum::simulated::SimulatedDevices;
+use donacdum::sink::AudioBackend;
+use donacdum::sink::AudioSink;
+use donacdum::sink::SinkFormat;
+use donacdum::stream::spawn_decoder_thread;
+use donacdum::stream::AudioStream;
+use donacdum::stream::DecoderControl;
+use donacdum::stream::DecoderThread;
+use donacdum::stream::DecoderThreadOptions;
+use donacdum::timestamp::parse_timestamp;
+use donacdum::wav::WavSink;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
-use win_core_audio::AudioClientShareMode;
-use win_core_audio::DataFlow;
-use win_core_audio::DeviceState;
-use win_core_audio::MultiMediaDeviceEnumerator;
-use winapi::shared::minwindef::FALSE;
-use winapi::shared::winerror::FAILED;
-use winapi::um::combaseapi::CoInitializeEx;
-use winapi::um::handleapi::CloseHandle;
-use winapi::um::objbase::COINIT_APARTMENTTHREADED;
-use winapi::um::synchapi::CreateEventW;
-use winapi::um::synchapi::WaitForSingleObject;
-
-const DONACDUM_MP3_BYTES: &[u8] =
-    include_bytes!("../assets/Payday 2 - DonAcDum EarRape-311954012.mp3");
-
-pub fn init_sta_com_runtime() -> std::io::Result<()> {
-    let code = unsafe { CoInitializeEx(std::ptr::null_mut(), COINIT_APARTMENTTHREADED) };
-    if FAILED(code) {
-        return Err(std::io::Error::from_raw_os_error(code));
-    }
-    Ok(())
+use std::time::Duration;
+
+/// Play audio on every active audio device
+#[derive(Debug, FromArgs)]
+struct Options {
+    /// the audio files, M3U/M3U8/PLS playlists or directories to play in order.
+    /// "-" reads from stdin. Defaults to the embedded DonAcDum clip.
+    #[argh(positional)]
+    inputs: Vec<PathBuf>,
+
+    /// the MIME type of the inputs, used as a hint when probing their format
+    #[argh(option)]
+    mime_type: Option<String>,
+
+    /// what to do with packets that fail to decode: "skip" replaces them with silence,
+    /// "abort" stops playback. Defaults to "skip".
+    #[argh(option, default = "DecodeErrorPolicy::Skip")]
+    on_decode_error: DecodeErrorPolicy,
+
+    /// where to start playing the first input, as [[hh:]mm:]ss[.fff]. Defaults to the loop start.
+    #[argh(option, from_str_fn(parse_timestamp))]
+    start: Option<Duration>,
+
+    /// where every input starts when it loops, as [[hh:]mm:]ss[.fff]. Defaults to the start.
+    #[argh(option, from_str_fn(parse_timestamp))]
+    loop_start: Option<Duration>,
+
+    /// where every input ends, as [[hh:]mm:]ss[.fff]. Defaults to the end.
+    #[argh(option, from_str_fn(parse_timestamp))]
+    loop_end: Option<Duration>,
+
+    /// the track to play from each input: "default", an index, "language:<language>"
+    /// or "codec:<codec>". Defaults to "default".
+    #[argh(option, default = "TrackSelector::Default")]
+    track: TrackSelector,
+
+    /// the track to play on one device, as <device index>=<track>. Overrides --track.
+    #[argh(option)]
+    device_track: Vec<DeviceTrack>,
+
+    /// the sample format of raw PCM on stdin, like "s16le" or "f32le".
+    /// Requires --raw-rate and --raw-channels.
+    #[argh(option)]
+    raw_format: Option<RawSampleFormat>,
+
+    /// the sample rate of raw PCM on stdin
+    #[argh(option)]
+    raw_rate: Option<u32>,
+
+    /// the number of channels of raw PCM on stdin
+    #[argh(option)]
+    raw_channels: Option<u8>,
+
+    /// how to convert audio to each device's sample rate: "sinc-best", "sinc-medium",
+    /// "sinc-fastest", "linear", "zero-order-hold", or "native" to require the device to
+    /// match the input's rate. Defaults to the config file, then "sinc-best".
+    #[argh(option)]
+    resample_quality: Option<ResampleQuality>,
+
+    /// a matrix that mixes the input channels onto every device's channels, with one row of
+    /// gains per device channel, like "1,0;0,1;0.5,0.5". Defaults to the config file,
+    /// then standard up-mixes and ITU down-mixes for each device's speakers.
+    #[argh(option)]
+    mix_matrix: Option<MixMatrix>,
+
+    /// how to dither audio for devices that play integer samples: "none", "tpdf", or "shaped"
+    /// for noise-shaped TPDF. Defaults to the config file, then "tpdf".
+    #[argh(option)]
+    dither: Option<DitherMode>,
+
+    /// a linear scale for the audio, applied before it is dithered for every device, the
+    /// --output file or the --pipe, like 0.5. Defaults to the config file, then 1.
+    #[argh(option)]
+    gain: Option<f32>,
+
+    /// play each device on its own clock. By default, devices are resampled slightly to keep them
+    /// in step with each other when playing on more than one.
+    #[argh(switch)]
+    no_drift_compensation: bool,
+
+    /// render to a WAVE file instead of playing on the audio devices
+    #[argh(option)]
+    output: Option<PathBuf>,
+
+    /// write raw interleaved PCM to a file or FIFO instead of playing on the audio devices.
+    /// "-" writes to stdout.
+    #[argh(option)]
+    pipe: Option<PathBuf>,
+
+    /// write --pipe output in real time, like an audio device would play it. By default it is
+    /// written as fast as the reader takes it.
+    #[argh(switch)]
+    real_time: bool,
+
+    /// the sample rate of the --output file or --pipe. Defaults to 48000.
+    #[argh(option, default = "48000")]
+    output_rate: u32,
+
+    /// the number of channels of the --output file or --pipe. Defaults to 2.
+    #[argh(option, default = "2")]
+    output_channels: u8,
+
+    /// the sample format of the --output file or --pipe: "s16le", "s24le", "s32le" or "f32le".
+    /// Defaults to "f32le".
+    #[argh(option, default = "RawSampleFormat::F32Le")]
+    output_format: RawSampleFormat,
+
+    /// play on simulated devices described by a TOML or JSON file, instead of the audio devices
+    #[argh(option)]
+    simulated_devices: Option<PathBuf>,
+
+    /// how much to play, as [[hh:]mm:]ss[.fff]
+    #[argh(option, from_str_fn(parse_timestamp))]
+    duration: Option<Duration>,
+
+    /// how many times to play the inputs. Defaults to looping forever on devices, and to once
+    /// for the --output file or --pipe unless --duration is set.
+    #[argh(option)]
+    loops: Option<u32>,
+
+    /// measure the speed and SNR of each resample quality and exit
+    #[argh(switch)]
+    resample_report: bool,
+
+    /// a TOML config file with default settings
+    #[argh(option)]
+    config: Option<PathBuf>,
+
+    /// list the tracks in each input and exit
+    #[argh(switch)]
+    list_tracks: bool,
 }
 
-pub struct Event(HANDLE);
+/// The track to play on one device
+#[derive(Debug)]
+struct DeviceTrack {
+    device: u32,
+    track: TrackSelector,
+}
 
-impl Event {
-    pub fn new() -> std::io::Result<Self> {
-        let handle =
-            unsafe { CreateEventW(std::ptr::null_mut(), FALSE, FALSE, std::ptr::null_mut()) };
-        if handle.is_null() {
-            return Err(std::io::Error::last_os_error());
-        }
+impl FromStr for DeviceTrack {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let index = input.find('=').with_context(|| {
+            format!(
+                "invalid device track '{}', expected <device>=<track>",
+                input
+            )
+        })?;
+
+        let device = input[..index]
+            .parse()
+            .with_context(|| format!("invalid device index '{}'", &input[..index]))?;
+        let track = input[index + 1..].parse()?;
 
-        Ok(Self(handle.cast()))
+        Ok(Self { device, track })
     }
 }
 
-impl Drop for Event {
-    fn drop(&mut self) {
-        unsafe {
-            CloseHandle(self.0.cast());
+/// Settings for converting audio to every device or output,
+/// from the command line or the config file
+#[derive(Debug, Clone)]
+struct OutputSettings {
+    resample_quality: ResampleQuality,
+    mix_matrix: Option<Arc<MixMatrix>>,
+    dither_mode: DitherMode,
+
+    /// A linear scale for the audio, applied before it is dithered
+    gain: f32,
+}
+
+/// Spawn a thread that reads playback commands from stdin.
+///
+/// Supported commands:
+/// * `seek <[[hh:]mm:]ss[.fff]>`: seek within the input that is currently playing
+fn spawn_command_thread(controls: Vec<DecoderControl>) {
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("Failed to read command: {}", e);
+                    return;
+                }
+            };
+
+            let mut args = line.split_whitespace();
+            match (args.next(), args.next(), args.next()) {
+                (None, _, _) => {}
+                (Some("seek"), Some(time), None) => match parse_timestamp(time) {
+                    Ok(time) => {
+                        for control in controls.iter() {
+                            if let Err(e) = control.seek(time) {
+                                eprintln!("Failed to seek: {:?}", e);
+                            }
+                        }
+                    }
+                    Err(e) => eprintln!("{}", e),
+                },
+                _ => eprintln!(
+                    "Unknown command '{}'. Usage: seek <[[hh:]mm:]ss[.fff]>",
+                    line
+                ),
+            }
         }
-    }
+    });
 }
 
 fn main() {
@@ -63,190 +268,488 @@ fn main() {
 }
 
 fn real_main() -> anyhow::Result<()> {
-    let (spec, raw_audio_buffer) =
-        decode_raw_audio_buffer().context("failed to decode raw audio buffer")?;
-    let raw_audio_buffer: Arc<Vec<_>> = Arc::from(raw_audio_buffer);
+    let options: Options = argh::from_env();
+
+    let config = match options.config.as_deref() {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let settings = OutputSettings {
+        resample_quality: options
+            .resample_quality
+            .or(config.resample_quality)
+            .unwrap_or_default(),
+        mix_matrix: options
+            .mix_matrix
+            .clone()
+            .or(config.mix_matrix)
+            .map(Arc::new),
+        dither_mode: options.dither.or(config.dither).unwrap_or_default(),
+        gain: options.gain.or(config.gain).unwrap_or(1.0),
+    };
+    if !(settings.gain.is_finite() && settings.gain >= 0.0) {
+        anyhow::bail!(
+            "the gain must be a finite number that is not negative, got {}",
+            settings.gain
+        );
+    }
+
+    if options.resample_report {
+        let (from_rate, to_rate) = (44100, 48000);
+        println!("Resampling {} Hz to {} Hz", from_rate, to_rate);
+        for quality in ResampleQuality::ALL.iter() {
+            match measure_quality(*quality, from_rate, to_rate)? {
+                Some(report) => println!("{}", report),
+                None => println!("{}: does not resample", quality),
+            }
+        }
+
+        return Ok(());
+    }
+
+    let raw_pcm = match (options.raw_format, options.raw_rate, options.raw_channels) {
+        (None, None, None) => None,
+        (Some(sample_format), Some(sample_rate), Some(num_channels)) => Some(RawPcmSpec {
+            sample_format,
+            sample_rate,
+            num_channels,
+        }),
+        _ => anyhow::bail!("--raw-format, --raw-rate and --raw-channels must be used together"),
+    };
+    if let Some(raw_pcm) = raw_pcm.as_ref() {
+        raw_pcm.validate().context("invalid raw PCM")?;
+    }
+
+    let inputs = if options.inputs.is_empty() {
+        vec![Input::Embedded]
+    } else {
+        let mut inputs = Vec::new();
+        for path in options.inputs.iter() {
+            if path.as_os_str() == "-" {
+                inputs.push(Input::Stdin { raw_pcm });
+            } else {
+                inputs.extend(load_inputs(path)?);
+            }
+        }
+        inputs
+    };
+    let reads_stdin = inputs.iter().any(|input| !input.is_repeatable());
+    if raw_pcm.is_some() && !reads_stdin {
+        anyhow::bail!("raw PCM can only be read from stdin, pass \"-\" as an input");
+    }
+
+    if options.list_tracks {
+        for input in inputs.iter() {
+            let tracks = input
+                .open()
+                .context("failed to open")
+                .and_then(|media_source| {
+                    probe_tracks(media_source, &input.hint(options.mime_type.as_deref()))
+                })
+                .with_context(|| format!("failed to probe '{}'", input))?;
+
+            for (index, track) in tracks.iter().enumerate() {
+                println!("Track {} in '{}'", index, input);
+                println!("{}", track);
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let (Some(loop_start), Some(loop_end)) = (options.loop_start, options.loop_end) {
+        if loop_start >= loop_end {
+            anyhow::bail!("the loop start must be before the loop end");
+        }
+    }
+
+    if options.loops == Some(0) {
+        anyhow::bail!("--loops must be at least 1");
+    }
+
+    let num_outputs = [
+        options.output.is_some(),
+        options.pipe.is_some(),
+        options.simulated_devices.is_some(),
+    ]
+    .iter()
+    .filter(|is_used| **is_used)
+    .count();
+    if num_outputs > 1 {
+        anyhow::bail!("only one of --output, --pipe and --simulated-devices can be used");
+    }
+
+    if let Some(output) = options.output.as_deref() {
+        let mut sink = WavSink::create(output, output_format(&options)?)?;
+        return render(&mut sink, &options, inputs, settings)
+            .with_context(|| format!("failed to render to '{}'", output.display()));
+    }
+
+    if let Some(pipe) = options.pipe.as_deref() {
+        let writer: Box<dyn Write> = if pipe.as_os_str() == "-" {
+            Box::new(std::io::stdout())
+        } else {
+            let file = File::create(pipe)
+                .with_context(|| format!("failed to open '{}'", pipe.display()))?;
+            Box::new(file)
+        };
+        let format = output_format(&options)?;
+
+        let result = if options.real_time {
+            let mut sink = PipeSink::paced(writer, format, SystemClock::new())?;
+            render(&mut sink, &options, inputs, settings)
+        } else {
+            let mut sink = PipeSink::new(writer, format);
+            render(&mut sink, &options, inputs, settings)
+        };
+        return result.with_context(|| format!("failed to write to '{}'", pipe.display()));
+    }
+
+    if let Some(path) = options.simulated_devices.as_deref() {
+        let devices = Arc::new(SimulatedDevices::load(path)?);
+        return play_on_devices(
+            move || Ok(SimulatedBackend::new(devices.clone(), SystemClock::new())),
+            &options,
+            inputs,
+            reads_stdin,
+            settings,
+        );
+    }
+
+    #[cfg(windows)]
+    return play_on_devices(WasapiBackend::new, &options, inputs, reads_stdin, settings);
 
-    // The stream is 2 channel f32 pcm here at 44.1 khz
-    eprintln!("Hertz: {}", spec.rate);
-    eprintln!("# of Channels: {}", spec.channels.count());
+    #[cfg(not(windows))]
+    anyhow::bail!(
+        "audio devices are only supported on Windows, use --output, --pipe or --simulated-devices"
+    );
+}
 
-    init_sta_com_runtime().context("failed to init com runtime")?;
+/// Get the decoder thread options for a track.
+fn decoder_thread_options(
+    options: &Options,
+    track: TrackSelector,
+    num_passes: Option<u32>,
+) -> DecoderThreadOptions {
+    DecoderThreadOptions {
+        mime_type: options.mime_type.clone(),
+        decode_options: DecodeOptions {
+            error_policy: options.on_decode_error,
+            track,
+        },
+        start: options.start,
+        loop_start: options.loop_start,
+        loop_end: options.loop_end,
+        num_passes,
+    }
+}
 
-    let device_enumerator =
-        MultiMediaDeviceEnumerator::new().context("failed to create device enumerator")?;
+/// Get the number of frames a duration lasts at a sample rate.
+fn duration_frames(duration: Duration, sample_rate: u32) -> u64 {
+    let num_frames = duration.as_nanos() * u128::from(sample_rate) / 1_000_000_000;
+    u64::try_from(num_frames).unwrap_or(u64::MAX)
+}
 
-    let audio_devices_collection = device_enumerator
-        .enum_audio_endpoints(DataFlow::Render, DeviceState::ACTIVE)
-        .context("failed to enumerate audio endpoints")?;
+/// Get the format of the --output file or --pipe.
+fn output_format(options: &Options) -> anyhow::Result<SinkFormat> {
+    let num_channels = usize::from(options.output_channels);
+    if num_channels == 0 {
+        anyhow::bail!("--output-channels must be at least 1");
+    }
+
+    Ok(SinkFormat {
+        sample_rate: options.output_rate,
+        channel_layout: default_layout(num_channels),
+        pcm_format: PcmFormat::packed(options.output_format, num_channels)?,
+    })
+}
+
+/// Play the inputs once on a sink that is not an audio device, like a file.
+fn render<S>(
+    sink: &mut S,
+    options: &Options,
+    inputs: Vec<Input>,
+    settings: OutputSettings,
+) -> anyhow::Result<()>
+where
+    S: AudioSink,
+{
+    if !options.device_track.is_empty() {
+        anyhow::bail!("--device-track can only be used with audio devices");
+    }
+
+    let format = sink.format();
+
+    // Without a duration, play the inputs once by default instead of forever.
+    let num_passes = match (options.loops, options.duration) {
+        (None, None) => Some(1),
+        (loops, _) => loops,
+    };
+    let max_frames = options
+        .duration
+        .map(|duration| duration_frames(duration, format.sample_rate));
+
+    let mut decoder_thread = spawn_decoder_thread(
+        inputs,
+        decoder_thread_options(options, options.track.clone(), num_passes),
+        1,
+    );
+    let receiver = decoder_thread.receivers.remove(0);
+
+    let mut audio_stream = AudioStream::new(
+        receiver,
+        ConversionKey {
+            sample_rate: format.sample_rate,
+            num_channels: format.pcm_format.num_channels(),
+            channel_layout: format.channel_layout,
+            quality: settings.resample_quality,
+            mix_matrix: settings.mix_matrix,
+        },
+    );
+    let mut dither = Dither::new(settings.dither_mode, 0);
+    let result = play(
+        sink,
+        &mut audio_stream,
+        &mut dither,
+        PlaybackOptions {
+            compensates_drift: false,
+            max_frames,
+            gain: settings.gain,
+        },
+    );
+
+    // Stop the decoder thread if the duration ran out first.
+    drop(audio_stream);
+    decoder_thread
+        .handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("decoder thread panicked"))?
+        .context("failed to decode audio")?;
+
+    result
+}
 
-    let num_audio_devices = audio_devices_collection
-        .get_count()
-        .context("failed to get # of audio devices")?;
+/// Play the inputs on every device of a backend.
+///
+/// Every device thread makes its own backend.
+/// Devices that fail are reported and left out, as long as one of them plays.
+fn play_on_devices<F, B>(
+    new_backend: F,
+    options: &Options,
+    inputs: Vec<Input>,
+    reads_stdin: bool,
+    settings: OutputSettings,
+) -> anyhow::Result<()>
+where
+    F: Fn() -> anyhow::Result<B> + Clone + Send + 'static,
+    B: AudioBackend,
+{
+    let backend = new_backend()?;
+    let devices = backend.devices()?;
+    let num_audio_devices = devices.len();
 
     eprintln!("Located {} audio devices", num_audio_devices);
+    if num_audio_devices == 0 {
+        anyhow::bail!("there are no active audio devices");
+    }
 
-    let mut handles = Vec::with_capacity(num_audio_devices.try_into().unwrap_or(0));
+    // Decode each distinct track once, for all the devices that play it.
+    let mut device_tracks = vec![options.track.clone(); num_audio_devices];
+    for device_track in options.device_track.iter() {
+        let track = device_tracks
+            .get_mut(usize::try_from(device_track.device).unwrap_or(usize::MAX))
+            .with_context(|| format!("missing audio device {}", device_track.device))?;
+        *track = device_track.track.clone();
+    }
 
-    let share_mode = AudioClientShareMode::Shared;
-    for i in 0..num_audio_devices {
-        let raw_audio_buffer = raw_audio_buffer.clone();
-        let handle = std::thread::spawn(move || {
-            init_sta_com_runtime().context("failed to init com runtime")?;
-
-            let device_enumerator =
-                MultiMediaDeviceEnumerator::new().context("failed to create device enumerator")?;
-
-            let audio_devices_collection = device_enumerator
-                .enum_audio_endpoints(DataFlow::Render, DeviceState::ACTIVE)
-                .context("failed to enumerate audio endpoints")?;
-
-            let audio_device = audio_devices_collection
-                .item(i)
-                .context("failed to get audio device")?;
-
-            /*
-            // Maximize pain
-            {
-                let audio_endpoint_volume = audio_device
-                    .activate_audio_endpoint_volume()
-                    .context("failed to get audio endpoint volume interface")?;
-                audio_endpoint_volume
-                    .set_master_volume_level_scalar(1.0)
-                    .context("failed to set master volume")?;
-                audio_endpoint_volume
-                    .set_mute(false)
-                    .context("failed to set mute")?;
-            }
-            */
-
-            let audio_client = audio_device
-                .activate_audio_client()
-                .context("failed to get audio client")?;
-
-            let (_default_period, minimum_period) = audio_client
-                .get_device_period()
-                .context("failed to get device period")?;
-
-            let mix_format = audio_client
-                .get_mix_format()
-                .context("failed to get mix format")?;
-
-            /*
-            let test_format = dbg!(WaveFormatExtensible::new(
-                WaveFormatType::Float,
-                spec.channels.count().try_into().unwrap(),
-                spec.rate,
-                32
-            ));
-
-            dbg!(audio_client.is_format_supported(share_mode, &test_format));
-            */
-
-            let audio_buffer = samplerate::convert(
-                spec.rate,
-                mix_format.samples_per_sec(),
-                mix_format.num_channels().into(),
-                samplerate::ConverterType::SincBestQuality,
-                &raw_audio_buffer,
-            )
-            .context("failed to convert audio buffer")?;
-
-            let mut audio_buffer_iter = audio_buffer.chunks(2);
-
-            audio_client
-                .initialize(share_mode, minimum_period, minimum_period, &mix_format)
-                .context("failed to initialize audio client")?;
-
-            let event_handle = Event::new().context("failed to make event handle")?;
-            audio_client
-                .set_event_handle(event_handle.0.cast())
-                .context("failed to set event handle")?;
-
-            let mut buffer_size = audio_client
-                .get_buffer_size()
-                .context("failed to get buffer size")?;
-
-            let render_client = audio_client
-                .get_service_audio_render_client()
-                .context("failed to get render client")?;
-
-            unsafe {
-                let ptr = render_client
-                    .get_buffer(buffer_size)
-                    .context("failed to get buffer")?;
-                for i in 0..(buffer_size as usize) {
-                    let data = audio_buffer_iter
-                        .next()
-                        .context("failed to preload buffer")?;
-                    ptr.cast::<f32>().add(i * 2).write(data[0]);
-                    ptr.cast::<f32>().add(i * 2 + 1).write(data[1]);
-                }
-                render_client
-                    .release_buffer(buffer_size)
-                    .context("failed to release buffer")?;
-            }
+    // Stdin can only be read by one decoder.
+    if reads_stdin && device_tracks.iter().any(|track| *track != device_tracks[0]) {
+        anyhow::bail!("every device must play the same track when reading from stdin");
+    }
 
-            audio_client.start().context("failed to start")?;
+    let mut decoder_threads: Vec<(TrackSelector, DecoderThread)> = Vec::new();
+    for track in device_tracks.iter() {
+        if decoder_threads
+            .iter()
+            .any(|(thread_track, _)| thread_track == track)
+        {
+            continue;
+        }
 
-            loop {
-                // Wait
-                unsafe {
-                    let ret = WaitForSingleObject(event_handle.0.cast(), 0xFFFFFFFF);
-                    // eprintln!("{:X}", ret);
-                }
+        let num_receivers = device_tracks.iter().filter(|t| *t == track).count();
+        let decoder_thread = spawn_decoder_thread(
+            inputs.clone(),
+            decoder_thread_options(options, track.clone(), options.loops),
+            num_receivers,
+        );
+        decoder_threads.push((track.clone(), decoder_thread));
+    }
 
-                // Update current buffer size
-                {
-                    buffer_size = audio_client
-                        .get_buffer_size()
-                        .context("failed to get buffer size")?;
-                    // println!("Buffer Size: {}", buffer_size);
-
-                    let current_padding = audio_client
-                        .get_current_padding()
-                        .context("failed to get current padding")?;
-                    // println!("Current Padding: {}", current_padding);
-                    buffer_size -= current_padding;
-                }
+    let mut receivers = Vec::with_capacity(device_tracks.len());
+    for track in device_tracks.iter() {
+        let (_, decoder_thread) = decoder_threads
+            .iter_mut()
+            .find(|(thread_track, _)| thread_track == track)
+            .expect("missing decoder thread");
+        receivers.push(decoder_thread.receivers.remove(0));
+    }
 
-                if buffer_size != 0 {
-                    unsafe {
-                        let ptr = render_client
-                            .get_buffer(buffer_size)
-                            .context("failed to get buffer")?;
-                        for i in 0..(buffer_size as usize) {
-                            let data = match audio_buffer_iter.next() {
-                                Some(data) => data,
-                                None => {
-                                    audio_buffer_iter = audio_buffer.chunks(2);
-                                    audio_buffer_iter
-                                        .next()
-                                        .expect("just refreshed iter is empty")
-                                }
-                            };
-                            ptr.cast::<f32>().add(i * 2).write(data[0]);
-                            ptr.cast::<f32>().add(i * 2 + 1).write(data[1]);
-                        }
-                        render_client
-                            .release_buffer(buffer_size)
-                            .context("failed to release buffer")?;
-                    }
-                }
-            }
+    // Stdin carries audio, so it can't carry commands too.
+    if !reads_stdin {
+        spawn_command_thread(
+            decoder_threads
+                .iter()
+                .map(|(_, decoder_thread)| decoder_thread.control.clone())
+                .collect(),
+        );
+    }
 
-            std::thread::sleep(std::time::Duration::from_millis(1000));
-            audio_client.stop().context("failed to stop")?;
+    let compensates_drift = !options.no_drift_compensation && num_audio_devices > 1;
 
-            Result::<_, anyhow::Error>::Ok(())
+    let mut handles = Vec::with_capacity(num_audio_devices);
+    for (i, receiver) in receivers.into_iter().enumerate() {
+        let new_backend = new_backend.clone();
+        let settings = settings.clone();
+        let duration = options.duration;
+        let handle = std::thread::spawn(move || {
+            let backend = new_backend()?;
+            let mut sink = backend.open(i)?;
+
+            let format = sink.format();
+            let mut audio_stream = AudioStream::new(
+                receiver,
+                ConversionKey {
+                    sample_rate: format.sample_rate,
+                    num_channels: format.pcm_format.num_channels(),
+                    channel_layout: format.channel_layout,
+                    quality: settings.resample_quality,
+                    mix_matrix: settings.mix_matrix,
+                },
+            );
+            let mut dither = Dither::new(settings.dither_mode, i as u64);
+
+            play(
+                &mut sink,
+                &mut audio_stream,
+                &mut dither,
+                PlaybackOptions {
+                    compensates_drift,
+                    max_frames: duration
+                        .map(|duration| duration_frames(duration, format.sample_rate)),
+                    gain: settings.gain,
+                },
+            )
         });
 
         handles.push(handle);
     }
 
-    for handle in handles {
-        let _ = handle.join().is_ok();
+    let mut num_failed_devices = 0;
+    for (handle, device) in handles.into_iter().zip(devices.iter()) {
+        let result = handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("device thread panicked"))
+            .and_then(|result| result);
+        if let Err(e) = result {
+            eprintln!("Failed to play on '{}': {:?}", device.name, e);
+            num_failed_devices += 1;
+        }
+    }
+
+    for (_, decoder_thread) in decoder_threads {
+        decoder_thread
+            .handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("decoder thread panicked"))?
+            .context("failed to decode audio")?;
+    }
+
+    if num_failed_devices == num_audio_devices {
+        anyhow::bail!("failed to play on every audio device");
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use donacdum::clock::VirtualClock;
+
+    const DEVICES: &str = r#"
+        [[devices]]
+        id = "speakers"
+        name = "Speakers"
+        mix_format = { sample_rate = 48000, num_channels = 2, sample_format = "f32le" }
+
+        [[devices]]
+        id = "headphones"
+        state = "disabled"
+        mix_format = { sample_rate = 48000, num_channels = 2, sample_format = "f32le" }
+
+        [[devices]]
+        id = "usb"
+        name = "USB Audio"
+        mix_format = { sample_rate = 44100, num_channels = 6, sample_format = "s16le" }
+    "#;
+
+    /// Play the first half second of the embedded clip once, on simulated devices.
+    fn play_simulated(devices: SimulatedDevices) -> anyhow::Result<()> {
+        let options = Options::from_args(
+            &["donacdum"],
+            &[
+                "--loop-end",
+                "0.5",
+                "--loops",
+                "1",
+                "--no-drift-compensation",
+            ],
+        )
+        .expect("invalid options");
+        let devices = Arc::new(devices);
+        play_on_devices(
+            move || Ok(SimulatedBackend::new(devices.clone(), VirtualClock::new())),
+            &options,
+            vec![Input::Embedded],
+            false,
+            OutputSettings {
+                resample_quality: ResampleQuality::Linear,
+                mix_matrix: None,
+                dither_mode: DitherMode::Tpdf,
+                gain: 1.0,
+            },
+        )
+    }
+
+    fn devices() -> SimulatedDevices {
+        toml::from_str(DEVICES).expect("failed to parse devices")
+    }
+
+    #[test]
+    fn plays_on_every_active_device() {
+        play_simulated(devices()).expect("failed to play");
+    }
+
+    #[test]
+    fn failing_devices_are_left_out() {
+        let mut devices = devices();
+        devices.devices[0].failures.open = Some("the device is in use".into());
+        devices.devices[2].failures.disconnect_after_frames = Some(4410);
+        assert!(play_simulated(devices.clone()).is_err());
+
+        // One working device is enough.
+        devices.devices[2].failures.disconnect_after_frames = None;
+        play_simulated(devices).expect("failed to play");
+    }
+
+    #[test]
+    fn needs_devices() {
+        let mut devices = devices();
+        devices.enumerate_error = Some("the audio service is not running".into());
+        assert!(play_simulated(devices).is_err());
+
+        let mut devices = self::devices();
+        devices.devices.clear();
+        assert!(play_simulated(devices).is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use anyhow::Context;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use symphonia::core::audio::Channels;
+
+/// The gain of a channel folded into two others, -3 dB
+const HALF_POWER: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// The most times a channel is folded into others when looking for a device channel
+const MAX_FOLD_DEPTH: usize = 4;
+
+/// Get the usual speaker positions of a device with a number of channels.
+///
+/// This is used when a device does not declare its speaker positions.
+pub fn default_layout(num_channels: usize) -> Channels {
+    let fronts = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+    let rears = Channels::REAR_LEFT | Channels::REAR_RIGHT;
+    let sides = Channels::SIDE_LEFT | Channels::SIDE_RIGHT;
+    match num_channels {
+        1 => Channels::FRONT_CENTRE,
+        2 => fronts,
+        3 => fronts | Channels::FRONT_CENTRE,
+        4 => fronts | rears,
+        5 => fronts | Channels::FRONT_CENTRE | rears,
+        6 => fronts | Channels::FRONT_CENTRE | Channels::LFE1 | rears,
+        7 => fronts | Channels::FRONT_CENTRE | Channels::LFE1 | Channels::REAR_CENTRE | sides,
+        8 => fronts | Channels::FRONT_CENTRE | Channels::LFE1 | rears | sides,
+        num_channels => {
+            let bits = 1_u64
+                .checked_shl(u32::try_from(num_channels).unwrap_or(u32::MAX))
+                .map_or(u64::MAX, |bit| bit - 1);
+            Channels::from_bits_truncate(bits as u32)
+        }
+    }
+}
+
+/// Get where to play a channel on a device that does not have it, best first.
+///
+/// Each alternative is a list of channels and gains.
+/// LFE channels are dropped, as in an ITU-R BS.775 down-mix.
+fn fold_alternatives(channel: Channels) -> &'static [&'static [(Channels, f32)]] {
+    match channel {
+        Channels::FRONT_LEFT => &[&[(Channels::FRONT_CENTRE, HALF_POWER)]],
+        Channels::FRONT_RIGHT => &[&[(Channels::FRONT_CENTRE, HALF_POWER)]],
+        Channels::FRONT_CENTRE => &[&[
+            (Channels::FRONT_LEFT, HALF_POWER),
+            (Channels::FRONT_RIGHT, HALF_POWER),
+        ]],
+        Channels::REAR_LEFT => &[
+            &[(Channels::SIDE_LEFT, 1.0)],
+            &[(Channels::FRONT_LEFT, HALF_POWER)],
+        ],
+        Channels::REAR_RIGHT => &[
+            &[(Channels::SIDE_RIGHT, 1.0)],
+            &[(Channels::FRONT_RIGHT, HALF_POWER)],
+        ],
+        Channels::SIDE_LEFT => &[
+            &[(Channels::REAR_LEFT, 1.0)],
+            &[(Channels::FRONT_LEFT, HALF_POWER)],
+        ],
+        Channels::SIDE_RIGHT => &[
+            &[(Channels::REAR_RIGHT, 1.0)],
+            &[(Channels::FRONT_RIGHT, HALF_POWER)],
+        ],
+        Channels::REAR_CENTRE => &[
+            &[
+                (Channels::REAR_LEFT, HALF_POWER),
+                (Channels::REAR_RIGHT, HALF_POWER),
+            ],
+            &[
+                (Channels::SIDE_LEFT, HALF_POWER),
+                (Channels::SIDE_RIGHT, HALF_POWER),
+            ],
+            &[(Channels::FRONT_LEFT, 0.5), (Channels::FRONT_RIGHT, 0.5)],
+        ],
+        Channels::FRONT_LEFT_CENTRE | Channels::FRONT_LEFT_WIDE => {
+            &[&[(Channels::FRONT_LEFT, 1.0)]]
+        }
+        Channels::FRONT_RIGHT_CENTRE | Channels::FRONT_RIGHT_WIDE => {
+            &[&[(Channels::FRONT_RIGHT, 1.0)]]
+        }
+        Channels::REAR_LEFT_CENTRE => &[&[(Channels::REAR_LEFT, 1.0)]],
+        Channels::REAR_RIGHT_CENTRE => &[&[(Channels::REAR_RIGHT, 1.0)]],
+        Channels::TOP_FRONT_LEFT | Channels::FRONT_LEFT_HIGH => {
+            &[&[(Channels::FRONT_LEFT, HALF_POWER)]]
+        }
+        Channels::TOP_FRONT_RIGHT | Channels::FRONT_RIGHT_HIGH => {
+            &[&[(Channels::FRONT_RIGHT, HALF_POWER)]]
+        }
+        Channels::TOP_CENTRE | Channels::TOP_FRONT_CENTRE | Channels::FRONT_CENTRE_HIGH => {
+            &[&[(Channels::FRONT_CENTRE, HALF_POWER)]]
+        }
+        Channels::TOP_REAR_LEFT => &[&[(Channels::REAR_LEFT, HALF_POWER)]],
+        Channels::TOP_REAR_RIGHT => &[&[(Channels::REAR_RIGHT, HALF_POWER)]],
+        Channels::TOP_REAR_CENTRE => &[&[(Channels::REAR_CENTRE, HALF_POWER)]],
+        _ => &[],
+    }
+}
+
+/// A matrix of gains from source channels to device channels
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "Vec<Vec<f32>>")]
+pub struct MixMatrix {
+    num_source_channels: usize,
+
+    /// The gains of the source channels, for each device channel
+    rows: Vec<Vec<f32>>,
+}
+
+impl MixMatrix {
+    /// Make a new [`MixMatrix`] from the gains of the source channels for each device channel.
+    ///
+    /// # Errors
+    /// Returns an error if the matrix is empty or its rows have different lengths.
+    pub fn new(rows: Vec<Vec<f32>>) -> anyhow::Result<Self> {
+        let num_source_channels = rows.first().map_or(0, Vec::len);
+        if num_source_channels == 0 {
+            anyhow::bail!("the mix matrix is empty");
+        }
+        if let Some(row) = rows.iter().find(|row| row.len() != num_source_channels) {
+            anyhow::bail!(
+                "the mix matrix has rows of {} and {} gains",
+                num_source_channels,
+                row.len()
+            );
+        }
+
+        Ok(Self {
+            num_source_channels,
+            rows,
+        })
+    }
+
+    /// Make the standard matrix from one speaker layout to another.
+    ///
+    /// A mono source is duplicated onto the front pair.
+    /// A stereo source is up-mixed by copying the front pair onto the surround channels at -3 dB,
+    /// leaving the centre and LFE silent so the stereo image is unchanged.
+    /// Channels the device does not have are folded into the nearest ones it does,
+    /// as in an ITU-R BS.775 down-mix.
+    ///
+    /// Device channels past its layout are silent.
+    pub fn standard(source: Channels, device: Channels, num_device_channels: usize) -> Self {
+        let source_channels: Vec<Channels> = source.iter().collect();
+        let device_channels: Vec<Channels> = device.iter().take(num_device_channels).collect();
+        let device = device_channels
+            .iter()
+            .fold(Channels::empty(), |device, channel| device | *channel);
+
+        let mut matrix = Self {
+            num_source_channels: source_channels.len(),
+            rows: vec![vec![0.0; source_channels.len()]; num_device_channels],
+        };
+
+        if source_channels.len() == 1 {
+            let fronts = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+            let targets = if device.contains(fronts) {
+                fronts
+            } else if device.contains(Channels::FRONT_CENTRE) {
+                Channels::FRONT_CENTRE
+            } else {
+                device_channels
+                    .first()
+                    .copied()
+                    .unwrap_or_else(Channels::empty)
+            };
+            for target in targets.iter() {
+                matrix.add(&device_channels, target, 0, 1.0);
+            }
+            return matrix;
+        }
+
+        for (column, channel) in source_channels.iter().enumerate() {
+            matrix.route(&device_channels, device, *channel, column, 1.0, 0);
+        }
+
+        if source == Channels::FRONT_LEFT | Channels::FRONT_RIGHT {
+            let surrounds = [
+                (Channels::REAR_LEFT, 0),
+                (Channels::SIDE_LEFT, 0),
+                (Channels::REAR_RIGHT, 1),
+                (Channels::SIDE_RIGHT, 1),
+            ];
+            for (target, column) in surrounds.iter() {
+                matrix.add(&device_channels, *target, *column, HALF_POWER);
+            }
+        }
+
+        matrix
+    }
+
+    /// Get the number of source channels.
+    pub fn num_source_channels(&self) -> usize {
+        self.num_source_channels
+    }
+
+    /// Get the number of device channels.
+    pub fn num_device_channels(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Mix interleaved source samples into interleaved device samples.
+    pub fn mix(&self, samples: &[f32], output: &mut Vec<f32>) {
+        output.reserve(samples.len() / self.num_source_channels * self.rows.len());
+        for frame in samples.chunks_exact(self.num_source_channels) {
+            for row in self.rows.iter() {
+                let sample = row
+                    .iter()
+                    .zip(frame.iter())
+                    .map(|(gain, sample)| gain * sample)
+                    .sum();
+                output.push(sample);
+            }
+        }
+    }
+
+    /// Route a source channel to a device channel, folding it into others if the device lacks it.
+    fn route(
+        &mut self,
+        device_channels: &[Channels],
+        device: Channels,
+        channel: Channels,
+        column: usize,
+        gain: f32,
+        depth: usize,
+    ) {
+        if device.contains(channel) {
+            self.add(device_channels, channel, column, gain);
+            return;
+        }
+        if depth == MAX_FOLD_DEPTH {
+            return;
+        }
+
+        let alternatives = fold_alternatives(channel);
+        let alternative = alternatives
+            .iter()
+            .find(|targets| targets.iter().all(|(target, _)| device.contains(*target)))
+            .or_else(|| alternatives.last());
+        for (target, target_gain) in alternative.into_iter().flat_map(|targets| targets.iter()) {
+            self.route(
+                device_channels,
+                device,
+                *target,
+                column,
+                gain * target_gain,
+                depth + 1,
+            );
+        }
+    }
+
+    /// Add a gain from a source channel to a device channel, if the device has it.
+    fn add(&mut self, device_channels: &[Channels], target: Channels, column: usize, gain: f32) {
+        if let Some(row) = device_channels
+            .iter()
+            .position(|channel| *channel == target)
+        {
+            self.rows[row][column] += gain;
+        }
+    }
+}
+
+impl TryFrom<Vec<Vec<f32>>> for MixMatrix {
+    type Error = anyhow::Error;
+
+    fn try_from(rows: Vec<Vec<f32>>) -> Result<Self, Self::Error> {
+        Self::new(rows)
+    }
+}
+
+impl FromStr for MixMatrix {
+    type Err = anyhow::Error;
+
+    /// Parse a matrix with rows separated by `;` and gains separated by `,`,
+    /// like `1,0;0,1;0.5,0.5`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let rows = input
+            .split(';')
+            .map(|row| {
+                row.split(',')
+                    .map(|gain| {
+                        gain.trim()
+                            .parse()
+                            .with_context(|| format!("invalid gain '{}'", gain.trim()))
+                    })
+                    .collect::<anyhow::Result<Vec<f32>>>()
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Self::new(rows)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STEREO: Channels = Channels::from_bits_truncate(0x3);
+    const SURROUND_5_1: Channels = Channels::from_bits_truncate(0x3f);
+    const SURROUND_7_1: Channels = Channels::from_bits_truncate(0x63f);
+
+    fn mix(matrix: &MixMatrix, frame: &[f32]) -> Vec<f32> {
+        let mut output = Vec::new();
+        matrix.mix(frame, &mut output);
+        output
+    }
+
+    #[test]
+    fn mono_is_duplicated() {
+        let matrix = MixMatrix::standard(Channels::FRONT_LEFT, SURROUND_5_1, 6);
+        assert_eq!(mix(&matrix, &[0.5]), [0.5, 0.5, 0.0, 0.0, 0.0, 0.0]);
+
+        let matrix = MixMatrix::standard(Channels::FRONT_LEFT, Channels::FRONT_CENTRE, 1);
+        assert_eq!(mix(&matrix, &[0.5]), [0.5]);
+    }
+
+    #[test]
+    fn itu_down_mix() {
+        let matrix = MixMatrix::standard(SURROUND_5_1, STEREO, 2);
+        let output = mix(&matrix, &[1.0, 2.0, 4.0, 8.0, 16.0, 32.0]);
+
+        assert!((output[0] - (1.0 + HALF_POWER * 4.0 + HALF_POWER * 16.0)).abs() < 1e-6);
+        assert!((output[1] - (2.0 + HALF_POWER * 4.0 + HALF_POWER * 32.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn side_and_rear_surrounds() {
+        // 7.1 to 5.1 folds the side channels into the rear ones.
+        let matrix = MixMatrix::standard(SURROUND_7_1, SURROUND_5_1, 6);
+        let output = mix(&matrix, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(output, [1.0, 2.0, 3.0, 4.0, 12.0, 14.0]);
+    }
+
+    #[test]
+    fn stereo_up_mix() {
+        let matrix = MixMatrix::standard(STEREO, SURROUND_7_1, 8);
+        let output = mix(&matrix, &[1.0, -1.0]);
+
+        assert_eq!(output[..4], [1.0, -1.0, 0.0, 0.0]);
+        for (sample, expected) in output[4..].iter().zip([1.0, -1.0, 1.0, -1.0].iter()) {
+            assert!((sample - expected * HALF_POWER).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn unpositioned_device_channels_are_silent() {
+        let matrix = MixMatrix::standard(STEREO, STEREO, 4);
+        assert_eq!(mix(&matrix, &[1.0, 2.0]), [1.0, 2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn custom_matrix() {
+        let matrix: MixMatrix = "0, 1; 1, 0; 0.5, 0.5".parse().expect("failed to parse");
+        assert_eq!(matrix.num_source_channels(), 2);
+        assert_eq!(matrix.num_device_channels(), 3);
+        assert_eq!(
+            mix(&matrix, &[1.0, 3.0, 2.0, 4.0]),
+            [3.0, 1.0, 2.0, 4.0, 2.0, 3.0]
+        );
+
+        assert!("1,0;1".parse::<MixMatrix>().is_err());
+        assert!("1,x".parse::<MixMatrix>().is_err());
+    }
+
+    #[test]
+    fn default_layouts() {
+        for num_channels in 1..=40 {
+            assert_eq!(
+                default_layout(num_channels).count(),
+                num_channels.min(26),
+                "{} channels",
+                num_channels
+            );
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::clock::Clock;
+use crate::clock::Playhead;
+use crate::sink::AudioSink;
+use crate::sink::SinkFormat;
+use anyhow::Context;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// A write to a [`NullSink`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkWrite {
+    /// When the write happened, by the sink's clock
+    pub time: Duration,
+
+    /// The number of frames written
+    pub num_frames: u32,
+
+    /// The number of frames that were free before the write
+    pub num_free_frames: u32,
+
+    /// The number of frames played as silence before the write
+    pub num_underrun_frames: u64,
+
+    /// Whether the sink was playing
+    pub is_started: bool,
+}
+
+/// A sink that plays nothing, at the pace of a WASAPI device on a clock.
+///
+/// The buffer is played a period at a time, and [`AudioSink::wait`] wakes up after each period.
+/// Everything written is kept, along with a record of each write.
+#[derive(Debug)]
+pub struct NullSink<C> {
+    format: SinkFormat,
+    playhead: Playhead<C>,
+
+    data: Vec<u8>,
+    writes: Vec<SinkWrite>,
+}
+
+impl<C> NullSink<C>
+where
+    C: Clock,
+{
+    /// Make a new [`NullSink`].
+    ///
+    /// # Errors
+    /// Returns an error if the period is zero or bigger than the buffer.
+    pub fn new(
+        format: SinkFormat,
+        buffer_frames: u32,
+        period_frames: u32,
+        clock: C,
+    ) -> anyhow::Result<Self> {
+        let playhead = Playhead::new(clock, format.sample_rate, buffer_frames, period_frames)?;
+
+        Ok(Self {
+            format,
+            playhead,
+
+            data: Vec::new(),
+            writes: Vec::new(),
+        })
+    }
+
+    /// Get the emulated device.
+    pub fn playhead(&self) -> &Playhead<C> {
+        &self.playhead
+    }
+
+    /// Get everything that was written.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Get a record of every write.
+    pub fn writes(&self) -> &[SinkWrite] {
+        &self.writes
+    }
+}
+
+impl<C> AudioSink for NullSink<C>
+where
+    C: Clock,
+{
+    fn format(&self) -> SinkFormat {
+        self.format
+    }
+
+    fn buffer_frames(&self) -> u32 {
+        self.playhead.buffer_frames()
+    }
+
+    fn wait(&mut self) -> anyhow::Result<u32> {
+        self.playhead.wait()
+    }
+
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let block_align = self.format.pcm_format.block_align();
+        if data.len() % block_align != 0 {
+            anyhow::bail!(
+                "got {} bytes, which is not a whole number of {} byte frames",
+                data.len(),
+                block_align
+            );
+        }
+
+        let num_frames = u32::try_from(data.len() / block_align).context("too many frames")?;
+        let num_free_frames = self.playhead.buffer_frames() - self.playhead.padding();
+        self.playhead.write(num_frames)?;
+
+        self.writes.push(SinkWrite {
+            time: self.playhead.clock().now(),
+            num_frames,
+            num_free_frames,
+            num_underrun_frames: self.playhead.num_underrun_frames(),
+            is_started: self.playhead.is_started(),
+        });
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn start(&mut self) -> anyhow::Result<()> {
+        self.playhead.start();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.playhead.stop();
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use crate::dither::Dither;
+use crate::raw::RawSampleFormat;
+
+/// How a device's samples are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PcmEncoding {
+    /// Signed little-endian integers
+    Int,
+
+    /// Little-endian IEEE floats
+    Float,
+}
+
+/// The layout of the interleaved samples an audio device plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PcmFormat {
+    encoding: PcmEncoding,
+    container_bits: u16,
+    valid_bits: u16,
+    num_channels: usize,
+    block_align: usize,
+}
+
+impl PcmFormat {
+    /// Make a new [`PcmFormat`].
+    ///
+    /// Each sample takes up `container_bits`, of which the top `valid_bits` are used,
+    /// so packed 24-bit audio has 24 of each and 24-in-32 has 32 and 24.
+    /// A frame is `block_align` bytes, which may leave padding after the samples.
+    ///
+    /// # Errors
+    /// Returns an error if the format cannot be written.
+    pub fn new(
+        encoding: PcmEncoding,
+        container_bits: u16,
+        valid_bits: u16,
+        num_channels: usize,
+        block_align: usize,
+    ) -> anyhow::Result<Self> {
+        let is_supported = match encoding {
+            PcmEncoding::Int => [16, 24, 32].contains(&container_bits),
+            PcmEncoding::Float => container_bits == 32 && valid_bits == 32,
+        };
+        if !is_supported || valid_bits == 0 || valid_bits > container_bits {
+            anyhow::bail!(
+                "unsupported sample format: {:?} with {} valid bits in {}",
+                encoding,
+                valid_bits,
+                container_bits
+            );
+        }
+        if num_channels == 0 {
+            anyhow::bail!("cannot write audio with no channels");
+        }
+
+        let min_block_align = num_channels * usize::from(container_bits / 8);
+        if block_align < min_block_align {
+            anyhow::bail!(
+                "a block align of {} bytes cannot fit {} channels of {} bit samples",
+                block_align,
+                num_channels,
+                container_bits
+            );
+        }
+
+        Ok(Self {
+            encoding,
+            container_bits,
+            valid_bits,
+            num_channels,
+            block_align,
+        })
+    }
+
+    /// Make a new packed float32 [`PcmFormat`].
+    pub fn float32(num_channels: usize) -> anyhow::Result<Self> {
+        Self::new(PcmEncoding::Float, 32, 32, num_channels, num_channels * 4)
+    }
+
+    /// Make a new packed [`PcmFormat`] from a raw sample format.
+    ///
+    /// # Errors
+    /// Returns an error if the format is not little-endian 16, 24 or 32-bit integers or 32-bit floats.
+    pub fn packed(sample_format: RawSampleFormat, num_channels: usize) -> anyhow::Result<Self> {
+        let (encoding, bits) = match sample_format {
+            RawSampleFormat::S16Le => (PcmEncoding::Int, 16),
+            RawSampleFormat::S24Le => (PcmEncoding::Int, 24),
+            RawSampleFormat::S32Le => (PcmEncoding::Int, 32),
+            RawSampleFormat::F32Le => (PcmEncoding::Float, 32),
+            _ => anyhow::bail!("cannot write {:?} samples", sample_format),
+        };
+        Self::new(
+            encoding,
+            bits,
+            bits,
+            num_channels,
+            num_channels * usize::from(bits / 8),
+        )
+    }
+
+    /// Get the sample encoding.
+    pub fn encoding(&self) -> PcmEncoding {
+        self.encoding
+    }
+
+    /// Get the number of bits each sample takes up.
+    pub fn bits_per_sample(&self) -> u16 {
+        self.container_bits
+    }
+
+    /// Get the number of bits of each sample that are used.
+    pub fn valid_bits_per_sample(&self) -> u16 {
+        self.valid_bits
+    }
+
+    /// Get the number of bytes each sample takes up.
+    pub fn bytes_per_sample(&self) -> usize {
+        usize::from(self.container_bits / 8)
+    }
+
+    /// Get the number of interleaved channels.
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Get the number of bytes in a frame.
+    pub fn block_align(&self) -> usize {
+        self.block_align
+    }
+
+    /// Write interleaved f32 samples into a buffer of frames in this format.
+    ///
+    /// Integer samples are dithered, rounded and clipped to the valid bits.
+    /// Unused bits and padding are zeroed.
+    ///
+    /// # Errors
+    /// Returns an error if the samples are not a whole number of frames,
+    /// or the buffer is not the size of that many frames.
+    pub fn write(
+        &self,
+        samples: &[f32],
+        output: &mut [u8],
+        dither: &mut Dither,
+    ) -> anyhow::Result<()> {
+        if samples.len() % self.num_channels != 0 {
+            anyhow::bail!(
+                "got {} samples, which is not a whole number of {} channel frames",
+                samples.len(),
+                self.num_channels
+            );
+        }
+        let num_frames = samples.len() / self.num_channels;
+        if output.len() != num_frames * self.block_align {
+            anyhow::bail!(
+                "a buffer of {} bytes does not fit {} frames of {} bytes",
+                output.len(),
+                num_frames,
+                self.block_align
+            );
+        }
+
+        let bytes_per_sample = self.bytes_per_sample();
+        for (frame, block) in samples
+            .chunks_exact(self.num_channels)
+            .zip(output.chunks_exact_mut(self.block_align))
+        {
+            let (block, padding) = block.split_at_mut(self.num_channels * bytes_per_sample);
+            for (channel, (sample, bytes)) in frame
+                .iter()
+                .zip(block.chunks_exact_mut(bytes_per_sample))
+                .enumerate()
+            {
+                match self.encoding {
+                    PcmEncoding::Int => {
+                        let value = self.quantize(*sample, channel, dither).to_le_bytes();
+                        bytes.copy_from_slice(&value[4 - bytes_per_sample..]);
+                    }
+                    PcmEncoding::Float => bytes.copy_from_slice(&sample.to_le_bytes()),
+                }
+            }
+            padding.iter_mut().for_each(|byte| *byte = 0);
+        }
+
+        Ok(())
+    }
+
+    /// Convert a sample to an integer, left-justified in 32 bits.
+    fn quantize(&self, sample: f32, channel: usize, dither: &mut Dither) -> i32 {
+        let scale = f64::from(1_u32 << (self.valid_bits - 1));
+        let value = dither
+            .quantize(channel, f64::from(sample) * scale)
+            .clamp(-scale, scale - 1.0) as i32;
+        value << (32 - self.valid_bits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dither::DitherMode;
+
+    fn write(format: PcmFormat, samples: &[f32]) -> Vec<u8> {
+        let num_frames = samples.len() / format.num_channels();
+        let mut output = vec![0xAA; num_frames * format.block_align()];
+        let mut dither = Dither::new(DitherMode::None, 0);
+        format
+            .write(samples, &mut output, &mut dither)
+            .expect("failed to write");
+        output
+    }
+
+    #[test]
+    fn int16() {
+        let format = PcmFormat::new(PcmEncoding::Int, 16, 16, 2, 4).expect("invalid format");
+        assert_eq!(
+            write(format, &[0.0, 0.5, -1.0, 1.0, -0.25, 2.0]),
+            [0x00, 0x00, 0x00, 0x40, 0x00, 0x80, 0xFF, 0x7F, 0x00, 0xE0, 0xFF, 0x7F]
+        );
+    }
+
+    #[test]
+    fn int24() {
+        let format = PcmFormat::new(PcmEncoding::Int, 24, 24, 1, 3).expect("invalid format");
+        assert_eq!(
+            write(format, &[0.5, -1.0, 1.0, 1.0 / 8_388_608.0]),
+            [0x00, 0x00, 0x40, 0x00, 0x00, 0x80, 0xFF, 0xFF, 0x7F, 0x01, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn int24_in_32() {
+        let format = PcmFormat::new(PcmEncoding::Int, 32, 24, 1, 4).expect("invalid format");
+        assert_eq!(
+            write(format, &[0.5, -1.0, 1.0, -1.0 / 8_388_608.0]),
+            [
+                0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x80, 0x00, 0xFF, 0xFF, 0x7F, 0x00, 0xFF,
+                0xFF, 0xFF
+            ]
+        );
+    }
+
+    #[test]
+    fn int32() {
+        let format = PcmFormat::new(PcmEncoding::Int, 32, 32, 1, 4).expect("invalid format");
+        assert_eq!(
+            write(format, &[-0.5, 1.0]),
+            [0x00, 0x00, 0x00, 0xC0, 0xFF, 0xFF, 0xFF, 0x7F]
+        );
+    }
+
+    #[test]
+    fn float32() {
+        let format = PcmFormat::float32(3).expect("invalid format");
+        assert_eq!(
+            write(format, &[1.0, -0.5, 2.0]),
+            [0x00, 0x00, 0x80, 0x3F, 0x00, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x00, 0x40]
+        );
+    }
+
+    #[test]
+    fn block_padding() {
+        // 3 channels of 16 bits, padded to 8 bytes a frame
+        let format = PcmFormat::new(PcmEncoding::Int, 16, 16, 3, 8).expect("invalid format");
+        assert_eq!(
+            write(format, &[0.5, 0.0, -0.5, 0.0, 0.5, 0.0]),
+            [
+                0x00, 0x40, 0x00, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00,
+                0x00, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn dithered_int16() {
+        // Dither moves samples by at most a step either way, the same way for the same seed.
+        let format = PcmFormat::new(PcmEncoding::Int, 16, 16, 2, 4).expect("invalid format");
+        let samples: Vec<f32> = (0..256).map(|i| (i as f32 - 128.0) / 65536.0).collect();
+        let write_dithered = |seed| {
+            let mut output = vec![0; samples.len() * 2];
+            let mut dither = Dither::new(DitherMode::Tpdf, seed);
+            format
+                .write(&samples, &mut output, &mut dither)
+                .expect("failed to write");
+            output
+        };
+
+        let output = write_dithered(5);
+        assert_eq!(output, write_dithered(5));
+        for (sample, bytes) in samples.iter().zip(output.chunks_exact(2)) {
+            let value = i16::from_le_bytes([bytes[0], bytes[1]]);
+            assert!((f32::from(value) - sample * 32768.0).abs() <= 1.5);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(PcmFormat::new(PcmEncoding::Int, 8, 8, 2, 2).is_err());
+        assert!(PcmFormat::new(PcmEncoding::Int, 16, 20, 2, 4).is_err());
+        assert!(PcmFormat::new(PcmEncoding::Float, 64, 64, 2, 16).is_err());
+        assert!(PcmFormat::new(PcmEncoding::Int, 24, 24, 2, 5).is_err());
+
+        let format = PcmFormat::float32(2).expect("invalid format");
+        let mut dither = Dither::new(DitherMode::None, 0);
+        assert!(format.write(&[0.0; 3], &mut [0; 12], &mut dither).is_err());
+        assert!(format.write(&[0.0; 4], &mut [0; 12], &mut dither).is_err());
+    }
+}
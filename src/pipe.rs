@@ -0,0 +1,266 @@
+use crate::clock::Clock;
+use crate::clock::Playhead;
+use crate::clock::SystemClock;
+use crate::sink::AudioSink;
+use crate::sink::SinkFormat;
+use anyhow::Context;
+use std::convert::TryFrom;
+use std::io::Write;
+
+/// The number of frames an unpaced [`PipeSink`] takes at once
+const BUFFER_FRAMES: u32 = 4096;
+
+/// The number of periods a paced [`PipeSink`] plays every second
+const PERIODS_PER_SECOND: u32 = 100;
+
+/// The number of periods a paced [`PipeSink`] buffers
+const BUFFER_PERIODS: u32 = 4;
+
+/// A sink that writes interleaved PCM, with no header, to a pipe, file or stdout.
+///
+/// It can be paced like an audio device, with writes running at most a few periods ahead
+/// of a clock, or write as fast as the reader takes it.
+pub struct PipeSink<W, C = SystemClock> {
+    writer: W,
+    format: SinkFormat,
+    playhead: Option<Playhead<C>>,
+}
+
+impl<W> PipeSink<W>
+where
+    W: Write,
+{
+    /// Make a new [`PipeSink`] that writes as fast as possible.
+    pub fn new(writer: W, format: SinkFormat) -> Self {
+        Self {
+            writer,
+            format,
+            playhead: None,
+        }
+    }
+}
+
+impl<W, C> PipeSink<W, C>
+where
+    W: Write,
+    C: Clock,
+{
+    /// Make a new [`PipeSink`] that writes in real time by a clock.
+    ///
+    /// # Errors
+    /// Returns an error if the sample rate is too low to split into periods.
+    pub fn paced(writer: W, format: SinkFormat, clock: C) -> anyhow::Result<Self> {
+        let period_frames = format.sample_rate / PERIODS_PER_SECOND;
+        if period_frames == 0 {
+            anyhow::bail!("the sample rate is too low to pace");
+        }
+        let playhead = Playhead::new(
+            clock,
+            format.sample_rate,
+            period_frames * BUFFER_PERIODS,
+            period_frames,
+        )?;
+
+        Ok(Self {
+            writer,
+            format,
+            playhead: Some(playhead),
+        })
+    }
+
+    /// Get the emulated device that paces writes, if there is one.
+    pub fn playhead(&self) -> Option<&Playhead<C>> {
+        self.playhead.as_ref()
+    }
+
+    /// Get the writer back.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W, C> AudioSink for PipeSink<W, C>
+where
+    W: Write,
+    C: Clock,
+{
+    fn format(&self) -> SinkFormat {
+        self.format
+    }
+
+    fn buffer_frames(&self) -> u32 {
+        match self.playhead.as_ref() {
+            Some(playhead) => playhead.buffer_frames(),
+            None => BUFFER_FRAMES,
+        }
+    }
+
+    fn wait(&mut self) -> anyhow::Result<u32> {
+        match self.playhead.as_mut() {
+            Some(playhead) => playhead.wait(),
+            None => Ok(BUFFER_FRAMES),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        if let Some(playhead) = self.playhead.as_mut() {
+            let num_frames = u32::try_from(data.len() / self.format.pcm_format.block_align())
+                .context("too many frames")?;
+            playhead.write(num_frames)?;
+        }
+
+        // Readers get every write at once, so paced output is not held back by buffering.
+        self.writer
+            .write_all(data)
+            .context("failed to write to pipe")?;
+        self.writer.flush().context("failed to flush pipe")
+    }
+
+    fn start(&mut self) -> anyhow::Result<()> {
+        if let Some(playhead) = self.playhead.as_mut() {
+            playhead.start();
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        if let Some(playhead) = self.playhead.as_mut() {
+            playhead.stop();
+        }
+        self.writer.flush().context("failed to flush pipe")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::convert::ConversionKey;
+    use crate::decoder::AudioChunk;
+    use crate::dither::Dither;
+    use crate::dither::DitherMode;
+    use crate::pcm::PcmFormat;
+    use crate::playback::play;
+    use crate::playback::PlaybackOptions;
+    use crate::raw::RawSampleFormat;
+    use crate::resample::ResampleQuality;
+    use crate::stream::AudioStream;
+    use crate::stream::ChunkReceiver;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use symphonia::core::audio::Channels;
+    use symphonia::core::audio::SignalSpec;
+
+    fn format() -> SinkFormat {
+        SinkFormat {
+            sample_rate: 48000,
+            channel_layout: Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+            pcm_format: PcmFormat::packed(RawSampleFormat::S16Le, 2).expect("invalid format"),
+        }
+    }
+
+    /// Get numbered frames.
+    fn frames(range: std::ops::Range<u32>) -> Vec<u8> {
+        range
+            .flat_map(|i| {
+                let sample = i as i16;
+                [sample.to_le_bytes(), sample.to_le_bytes()].concat()
+            })
+            .collect()
+    }
+
+    /// Play numbered frames through the playback loop.
+    fn play_frames<W, C>(sink: &mut PipeSink<W, C>, num_frames: u32)
+    where
+        W: Write,
+        C: Clock,
+    {
+        let channel_layout = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+        let (sender, receiver) = ChunkReceiver::channel();
+        for chunk_frames in frames(0..num_frames).chunks(400) {
+            let samples = chunk_frames
+                .chunks_exact(2)
+                .map(|sample| f32::from(i16::from_le_bytes([sample[0], sample[1]])) / 32768.0)
+                .collect();
+            let chunk = AudioChunk {
+                spec: SignalSpec::new(48000, channel_layout),
+                samples,
+            };
+            sender.send((0, Arc::new(chunk))).expect("failed to send");
+        }
+        drop(sender);
+
+        let mut audio_stream = AudioStream::new(
+            receiver,
+            ConversionKey {
+                sample_rate: 48000,
+                num_channels: 2,
+                channel_layout,
+                quality: ResampleQuality::SincBest,
+                mix_matrix: None,
+            },
+        );
+        let mut dither = Dither::new(DitherMode::None, 0);
+        play(
+            sink,
+            &mut audio_stream,
+            &mut dither,
+            PlaybackOptions::default(),
+        )
+        .expect("failed to play");
+    }
+
+    /// Write a second of numbered frames, as the playback loop would.
+    fn write_second<S>(sink: &mut S)
+    where
+        S: AudioSink,
+    {
+        let mut num_frames_written = sink.buffer_frames();
+        sink.write(&frames(0..num_frames_written))
+            .expect("failed to write");
+        sink.start().expect("failed to start");
+        while num_frames_written < 48000 {
+            let num_free_frames = sink.wait().expect("failed to wait");
+            let end = (num_frames_written + num_free_frames).min(48000);
+            sink.write(&frames(num_frames_written..end))
+                .expect("failed to write");
+            num_frames_written = end;
+        }
+        sink.stop().expect("failed to stop");
+    }
+
+    #[test]
+    fn unpaced_writes_everything_at_once() {
+        let mut sink = PipeSink::new(Vec::new(), format());
+        write_second(&mut sink);
+        assert!(sink.playhead().is_none());
+        assert_eq!(sink.into_inner(), frames(0..48000));
+    }
+
+    #[test]
+    fn paced_writes_in_real_time() {
+        let clock = VirtualClock::new();
+        let mut sink =
+            PipeSink::paced(Vec::new(), format(), clock.clone()).expect("failed to make sink");
+        write_second(&mut sink);
+
+        // The last writes run a buffer ahead of the clock.
+        assert_eq!(clock.now(), Duration::from_millis(1000 - 40));
+        let playhead = sink.playhead().expect("missing playhead");
+        assert_eq!(playhead.num_underrun_frames(), 0);
+        assert_eq!(sink.into_inner(), frames(0..48000));
+    }
+
+    #[test]
+    fn plays_to_the_end_of_the_stream() {
+        // The stream ends part of the way through a buffer, and nothing is written after it.
+        let mut sink = PipeSink::new(Vec::new(), format());
+        play_frames(&mut sink, 3000);
+        assert_eq!(sink.into_inner(), frames(0..3000));
+
+        let mut sink = PipeSink::paced(Vec::new(), format(), VirtualClock::new())
+            .expect("failed to make sink");
+        play_frames(&mut sink, 3000);
+        assert_eq!(sink.into_inner(), frames(0..3000));
+    }
+}
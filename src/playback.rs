@@ -0,0 +1,323 @@
+use crate::dither::Dither;
+use crate::drift::DriftCompensator;
+use crate::sink::AudioSink;
+use crate::stream::AudioStream;
+use anyhow::Context;
+use std::convert::TryFrom;
+use std::time::Instant;
+
+/// Options for playing a stream on a sink
+#[derive(Debug, Clone, Copy)]
+pub struct PlaybackOptions {
+    /// Resample the stream slightly to keep the sink in step with the wall clock
+    pub compensates_drift: bool,
+
+    /// Stop after playing this many frames, even if the stream has more
+    pub max_frames: Option<u64>,
+
+    /// A linear scale for every sample, applied before it is dithered and written
+    pub gain: f32,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        Self {
+            compensates_drift: false,
+            max_frames: None,
+            gain: 1.0,
+        }
+    }
+}
+
+/// Play a stream on a sink until the stream ends, or the frame limit is reached,
+/// and the sink has played it all.
+///
+/// The sink's buffer is filled before it starts,
+/// then topped up every time it has played some of it.
+///
+/// # Errors
+/// Returns an error if the stream could not be read or the sink failed.
+pub fn play<S>(
+    sink: &mut S,
+    audio_stream: &mut AudioStream,
+    dither: &mut Dither,
+    options: PlaybackOptions,
+) -> anyhow::Result<()>
+where
+    S: AudioSink + ?Sized,
+{
+    let format = sink.format();
+    let num_channels = format.pcm_format.num_channels();
+    let block_align = format.pcm_format.block_align();
+    let buffer_frames = sink.buffer_frames();
+
+    let mut drift_compensator = if options.compensates_drift {
+        let drift_compensator = DriftCompensator::new(format.sample_rate);
+        audio_stream
+            .set_rate_correction(drift_compensator.correction())
+            .context("failed to set rate correction")?;
+        Some(drift_compensator)
+    } else {
+        None
+    };
+
+    // Audio is read as f32, scaled by the gain, then written in the sink's format.
+    // Only the frames that were read are written, so the stream's end is not padded with silence.
+    let mut samples = Vec::new();
+    let mut data = Vec::new();
+    let mut fill = |sink: &mut S, audio_stream: &mut AudioStream, num_frames: u32| {
+        samples.resize(num_frames as usize * num_channels, 0.0);
+        let num_frames_read = audio_stream
+            .read(&mut samples)
+            .context("failed to fill buffer")?;
+        if num_frames_read != 0 {
+            samples.truncate(num_frames_read * num_channels);
+            if options.gain != 1.0 {
+                for sample in samples.iter_mut() {
+                    *sample *= options.gain;
+                }
+            }
+            data.resize(num_frames_read * block_align, 0);
+            format.pcm_format.write(&samples, &mut data, dither)?;
+            sink.write(&data).context("failed to write to sink")?;
+        }
+
+        // This is at most the number of frames asked for, so it fits.
+        anyhow::Result::<_>::Ok(num_frames_read as u32)
+    };
+
+    // The number of frames that can be written before reaching the limit
+    let frames_left = |num_frames_written: u64| match options.max_frames {
+        Some(max_frames) => max_frames.saturating_sub(num_frames_written),
+        None => u64::MAX,
+    };
+
+    let num_frames = buffer_frames.min(u32::try_from(frames_left(0)).unwrap_or(u32::MAX));
+    let num_frames_read = fill(sink, audio_stream, num_frames).context("failed to preload")?;
+    let mut num_frames_written = u64::from(num_frames_read);
+    let mut is_running = num_frames_read == num_frames && frames_left(num_frames_written) != 0;
+    sink.start().context("failed to start")?;
+    let start = Instant::now();
+
+    while is_running {
+        let num_free_frames = sink.wait().context("failed to wait for the sink")?;
+
+        // The device has played everything written before what is still buffered.
+        if let Some(drift_compensator) = drift_compensator.as_mut() {
+            let num_buffered_frames = buffer_frames.saturating_sub(num_free_frames);
+            let num_frames_consumed =
+                num_frames_written.saturating_sub(u64::from(num_buffered_frames));
+            let correction = drift_compensator.update(start.elapsed(), num_frames_consumed);
+            audio_stream
+                .set_rate_correction(correction)
+                .context("failed to set rate correction")?;
+        }
+
+        let num_frames =
+            num_free_frames.min(u32::try_from(frames_left(num_frames_written)).unwrap_or(u32::MAX));
+        if num_frames != 0 {
+            let num_frames_read = fill(sink, audio_stream, num_frames)?;
+            num_frames_written += u64::from(num_frames_read);
+            is_running = num_frames_read == num_frames;
+        }
+        is_running &= frames_left(num_frames_written) != 0;
+    }
+
+    // Let the device play out what is left before stopping.
+    while sink.wait().context("failed to wait for the sink")? < buffer_frames {}
+    sink.stop().context("failed to stop")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::clock::VirtualClock;
+    use crate::convert::ConversionKey;
+    use crate::decoder::AudioChunk;
+    use crate::dither::DitherMode;
+    use crate::null::NullSink;
+    use crate::pcm::PcmEncoding;
+    use crate::pcm::PcmFormat;
+    use crate::resample::ResampleQuality;
+    use crate::sink::MemorySink;
+    use crate::sink::SinkFormat;
+    use crate::stream::ChunkReceiver;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use symphonia::core::audio::Channels;
+    use symphonia::core::audio::SignalSpec;
+
+    /// A stereo stream of steps that are 100 frames long
+    fn stream(sample_rate: u32, num_frames: usize) -> AudioStream {
+        let channel_layout = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+        let (sender, receiver) = ChunkReceiver::channel();
+        std::thread::spawn(move || {
+            for i in 0..num_frames / 100 {
+                let chunk = AudioChunk {
+                    spec: SignalSpec::new(sample_rate, channel_layout),
+                    samples: vec![(i % 16) as f32 / 16.0; 200],
+                };
+                sender.send((0, Arc::new(chunk))).expect("failed to send");
+            }
+        });
+
+        AudioStream::new(
+            receiver,
+            ConversionKey {
+                sample_rate,
+                num_channels: 2,
+                channel_layout,
+                quality: ResampleQuality::SincBest,
+                mix_matrix: None,
+            },
+        )
+    }
+
+    fn sink_format(sample_rate: u32) -> SinkFormat {
+        SinkFormat {
+            sample_rate,
+            channel_layout: Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+            pcm_format: PcmFormat::new(PcmEncoding::Int, 16, 16, 2, 4).expect("invalid format"),
+        }
+    }
+
+    /// Check that the data is the stream's frames followed by silence.
+    fn check_data(data: &[u8], num_frames: usize) {
+        for (i, frame) in data.chunks_exact(4).enumerate() {
+            let expected = if i < num_frames {
+                ((i / 100) % 16) as i16 * 2048
+            } else {
+                0
+            };
+            assert_eq!(
+                frame,
+                [expected.to_le_bytes(), expected.to_le_bytes()].concat(),
+                "frame {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn plays_whole_stream() {
+        let mut audio_stream = stream(48000, 1000);
+        let mut sink = MemorySink::new(sink_format(48000), 256);
+        let mut dither = Dither::new(DitherMode::None, 0);
+        play(
+            &mut sink,
+            &mut audio_stream,
+            &mut dither,
+            PlaybackOptions::default(),
+        )
+        .expect("failed to play");
+        assert!(!sink.is_started());
+
+        // Only the stream's frames, without silence at the end
+        assert_eq!(sink.data().len(), 1000 * 4);
+        check_data(sink.data(), 1000);
+    }
+
+    #[test]
+    fn stops_at_frame_limit() {
+        for max_frames in [0, 100, 256, 700].iter() {
+            let mut audio_stream = stream(48000, 1000);
+            let mut sink = MemorySink::new(sink_format(48000), 256);
+            let mut dither = Dither::new(DitherMode::None, 0);
+            play(
+                &mut sink,
+                &mut audio_stream,
+                &mut dither,
+                PlaybackOptions {
+                    max_frames: Some(*max_frames),
+                    ..PlaybackOptions::default()
+                },
+            )
+            .expect("failed to play");
+
+            assert_eq!(sink.data().len(), *max_frames as usize * 4);
+            check_data(sink.data(), 1000);
+        }
+    }
+
+    #[test]
+    fn applies_gain() {
+        let mut audio_stream = stream(48000, 1000);
+        let mut sink = MemorySink::new(sink_format(48000), 256);
+        let mut dither = Dither::new(DitherMode::None, 0);
+        play(
+            &mut sink,
+            &mut audio_stream,
+            &mut dither,
+            PlaybackOptions {
+                gain: 0.5,
+                ..PlaybackOptions::default()
+            },
+        )
+        .expect("failed to play");
+
+        // Halved before it is written as integers
+        for (i, frame) in sink.data().chunks_exact(4).enumerate() {
+            let expected = ((i / 100) % 16) as i16 * 1024;
+            assert_eq!(
+                frame,
+                [expected.to_le_bytes(), expected.to_le_bytes()].concat(),
+                "frame {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn refills_what_the_device_played() {
+        // Buffers that are and are not whole periods, wrapping around many times
+        let configs = [(48000, 1056, 480), (44100, 1000, 441), (48000, 480, 480)];
+        for (sample_rate, buffer_frames, period_frames) in configs.iter().copied() {
+            let clock = VirtualClock::new();
+            let mut audio_stream = stream(sample_rate, 20_000);
+            let mut sink = NullSink::new(
+                sink_format(sample_rate),
+                buffer_frames,
+                period_frames,
+                clock.clone(),
+            )
+            .expect("failed to make sink");
+            let mut dither = Dither::new(DitherMode::None, 0);
+            play(
+                &mut sink,
+                &mut audio_stream,
+                &mut dither,
+                PlaybackOptions::default(),
+            )
+            .expect("failed to play");
+
+            // The whole buffer is preloaded, then every wake-up tops up the period that was played,
+            // until the last write of what is left.
+            let writes = sink.writes();
+            assert!(!writes[0].is_started);
+            assert_eq!(writes[0].num_frames, buffer_frames);
+            let (last, writes) = writes[1..].split_last().expect("missing writes");
+            for write in writes.iter() {
+                assert!(write.is_started);
+                assert_eq!(write.num_free_frames, period_frames);
+                assert_eq!(write.num_frames, period_frames);
+                assert_eq!(write.num_underrun_frames, 0);
+            }
+            assert!(last.num_frames <= period_frames);
+            assert_eq!(last.num_underrun_frames, 0);
+
+            // Nothing is lost or repeated, and everything was played before stopping.
+            let num_frames_written = sink.data().len() / 4;
+            assert_eq!(num_frames_written, 20_000);
+            check_data(sink.data(), 20_000);
+            assert!(!sink.playhead().is_started());
+            assert_eq!(sink.playhead().padding(), 0);
+
+            let duration = Duration::from_secs_f64(num_frames_written as f64 / sample_rate as f64);
+            assert!(clock.now() >= duration);
+            assert!(clock.now() < duration + Duration::from_millis(20));
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::input::Input;
+use anyhow::Context;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// File extensions of audio files that are played from directories.
+///
+/// Other files in a directory, like cover art, are ignored.
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "aac", "aif", "aifc", "aiff", "caf", "flac", "m4a", "mka", "mkv", "mp1", "mp2", "mp3", "mp4",
+    "oga", "ogg", "wav", "wave", "webm",
+];
+
+/// Get the inputs to play for a path.
+///
+/// Playlists (`.m3u`, `.m3u8` and `.pls`) are expanded into their entries,
+/// with relative paths resolved against the playlist's directory.
+/// Directories are expanded into the audio files they contain, sorted by name.
+/// Anything else is a single input.
+///
+/// Entries are not checked here; missing files are reported when they are played.
+///
+/// # Errors
+/// Returns an error if a playlist or directory could not be read.
+pub fn load_inputs(path: &Path) -> anyhow::Result<Vec<Input>> {
+    if path.is_dir() {
+        return load_directory(path)
+            .with_context(|| format!("failed to read directory '{}'", path.display()));
+    }
+
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    let parse = match extension.as_deref() {
+        Some("m3u") | Some("m3u8") => parse_m3u,
+        Some("pls") => parse_pls,
+        _ => return Ok(vec![Input::File(path.into())]),
+    };
+
+    let data = std::fs::read(path)
+        .with_context(|| format!("failed to read playlist '{}'", path.display()))?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let paths = parse(&String::from_utf8_lossy(&data), base)
+        .with_context(|| format!("failed to parse playlist '{}'", path.display()))?;
+
+    Ok(paths.into_iter().map(Input::File).collect())
+}
+
+/// Get the audio files in a directory, sorted by name.
+fn load_directory(path: &Path) -> anyhow::Result<Vec<Input>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        let is_audio = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                AUDIO_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+            });
+        if is_audio && entry.file_type()?.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths.into_iter().map(Input::File).collect())
+}
+
+/// Parse an M3U or M3U8 playlist.
+///
+/// Comments and extended M3U directives are ignored.
+fn parse_m3u(data: &str, base: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    Ok(data
+        .lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect())
+}
+
+/// Parse a PLS playlist.
+///
+/// Entries are ordered by their number, not by where they appear in the file.
+fn parse_pls(data: &str, base: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    for line in data.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        let (key, value) = match line.find('=') {
+            Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+            None => continue,
+        };
+
+        let is_file_key = key.len() > 4
+            && key
+                .get(..4)
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case("file"));
+        if !is_file_key {
+            continue;
+        }
+
+        let index: u32 = key[4..]
+            .parse()
+            .with_context(|| format!("invalid entry key '{}'", key))?;
+        entries.push((index, base.join(value)));
+    }
+    entries.sort_by_key(|(index, _)| *index);
+
+    Ok(entries.into_iter().map(|(_, path)| path).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn m3u() {
+        let data = "\u{feff}#EXTM3U\n#EXTINF:123,Artist - Title\nfirst.mp3\r\n\n  sub/second.flac  \n/absolute.ogg\n";
+        let paths = parse_m3u(data, Path::new("/music/playlists")).expect("failed to parse");
+
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("/music/playlists/first.mp3"),
+                PathBuf::from("/music/playlists/sub/second.flac"),
+                PathBuf::from("/absolute.ogg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn pls() {
+        let data = "[playlist]\nFile2=second.mp3\nTitle2=Second\nfile1=first.mp3\nNumberOfEntries=2\nVersion=2\n";
+        let paths = parse_pls(data, Path::new("playlists")).expect("failed to parse");
+
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("playlists/first.mp3"),
+                PathBuf::from("playlists/second.mp3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn pls_invalid_key() {
+        assert!(parse_pls("[playlist]\nFileA=first.mp3\n", Path::new("")).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+use serde::Deserialize;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::str::FromStr;
+use symphonia::core::audio::Channels;
+use symphonia::core::codecs::CodecParameters;
+use symphonia::core::codecs::CodecType;
+use symphonia::core::codecs::CODEC_TYPE_PCM_F32BE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_F32LE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_F64BE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_F64LE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_S16BE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_S16LE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_S24BE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_S24LE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_S32BE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_S32LE;
+use symphonia::core::codecs::CODEC_TYPE_PCM_S8;
+use symphonia::core::codecs::CODEC_TYPE_PCM_U8;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::Cue;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::formats::FormatReader;
+use symphonia::core::formats::Packet;
+use symphonia::core::formats::SeekMode;
+use symphonia::core::formats::SeekTo;
+use symphonia::core::formats::SeekedTo;
+use symphonia::core::formats::Track;
+use symphonia::core::io::MediaSource;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::io::ReadBytes;
+use symphonia::core::meta::Metadata;
+use symphonia::core::meta::MetadataLog;
+use symphonia::core::units::TimeBase;
+
+/// The number of frames in each packet of raw PCM
+const FRAMES_PER_PACKET: u64 = 1152;
+
+/// The sample format of raw PCM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RawSampleFormat {
+    U8,
+    S8,
+    S16Le,
+    S16Be,
+    S24Le,
+    S24Be,
+    S32Le,
+    S32Be,
+    F32Le,
+    F32Be,
+    F64Le,
+    F64Be,
+}
+
+impl RawSampleFormat {
+    /// Get the symphonia codec that decodes this format.
+    fn codec(self) -> CodecType {
+        match self {
+            Self::U8 => CODEC_TYPE_PCM_U8,
+            Self::S8 => CODEC_TYPE_PCM_S8,
+            Self::S16Le => CODEC_TYPE_PCM_S16LE,
+            Self::S16Be => CODEC_TYPE_PCM_S16BE,
+            Self::S24Le => CODEC_TYPE_PCM_S24LE,
+            Self::S24Be => CODEC_TYPE_PCM_S24BE,
+            Self::S32Le => CODEC_TYPE_PCM_S32LE,
+            Self::S32Be => CODEC_TYPE_PCM_S32BE,
+            Self::F32Le => CODEC_TYPE_PCM_F32LE,
+            Self::F32Be => CODEC_TYPE_PCM_F32BE,
+            Self::F64Le => CODEC_TYPE_PCM_F64LE,
+            Self::F64Be => CODEC_TYPE_PCM_F64BE,
+        }
+    }
+
+    /// Get the number of bits in a sample.
+    pub fn bits_per_sample(self) -> u32 {
+        match self {
+            Self::U8 | Self::S8 => 8,
+            Self::S16Le | Self::S16Be => 16,
+            Self::S24Le | Self::S24Be => 24,
+            Self::S32Le | Self::S32Be | Self::F32Le | Self::F32Be => 32,
+            Self::F64Le | Self::F64Be => 64,
+        }
+    }
+}
+
+impl FromStr for RawSampleFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "u8" => Ok(Self::U8),
+            "s8" => Ok(Self::S8),
+            "s16le" => Ok(Self::S16Le),
+            "s16be" => Ok(Self::S16Be),
+            "s24le" => Ok(Self::S24Le),
+            "s24be" => Ok(Self::S24Be),
+            "s32le" => Ok(Self::S32Le),
+            "s32be" => Ok(Self::S32Be),
+            "f32le" => Ok(Self::F32Le),
+            "f32be" => Ok(Self::F32Be),
+            "f64le" => Ok(Self::F64Le),
+            "f64be" => Ok(Self::F64Be),
+            _ => anyhow::bail!("invalid raw sample format '{}'", input),
+        }
+    }
+}
+
+/// The layout of raw, headerless PCM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RawPcmSpec {
+    /// The format of each sample
+    pub sample_format: RawSampleFormat,
+
+    /// The sample rate
+    pub sample_rate: u32,
+
+    /// The number of interleaved channels.
+    ///
+    /// Channels are assigned in WAVE order, starting with front left.
+    pub num_channels: u8,
+}
+
+impl RawPcmSpec {
+    /// Check that the spec can be decoded.
+    ///
+    /// # Errors
+    /// Returns an error if the sample rate is zero, or there are no channels or more than 32.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.sample_rate == 0 {
+            anyhow::bail!("the raw sample rate must not be zero");
+        }
+        if self.num_channels == 0 || self.num_channels > 32 {
+            anyhow::bail!("invalid number of raw channels {}", self.num_channels);
+        }
+
+        Ok(())
+    }
+
+    /// Get the number of bytes in a frame.
+    fn bytes_per_frame(&self) -> u64 {
+        u64::from(self.sample_format.bits_per_sample() / 8) * u64::from(self.num_channels)
+    }
+}
+
+/// A format reader for raw, headerless PCM.
+///
+/// The stream has a single track and no metadata.
+/// Non-seekable sources can only seek forwards.
+pub struct RawPcmReader {
+    source: MediaSourceStream,
+    tracks: Vec<Track>,
+    cues: Vec<Cue>,
+    metadata: MetadataLog,
+
+    bytes_per_frame: u64,
+    ts: u64,
+}
+
+impl RawPcmReader {
+    /// Make a new [`RawPcmReader`].
+    ///
+    /// # Errors
+    /// Returns an error if the spec's sample rate is zero, or it has no channels or more than 32.
+    pub fn new(source: MediaSourceStream, spec: &RawPcmSpec) -> anyhow::Result<Self> {
+        spec.validate()?;
+
+        let channels = Channels::from_bits_truncate(((1u64 << spec.num_channels) - 1) as u32);
+        let mut codec_params = CodecParameters::new();
+        codec_params
+            .for_codec(spec.sample_format.codec())
+            .with_sample_rate(spec.sample_rate)
+            .with_time_base(TimeBase::new(1, spec.sample_rate))
+            .with_bits_per_sample(spec.sample_format.bits_per_sample())
+            .with_channels(channels)
+            .with_max_frames_per_packet(FRAMES_PER_PACKET);
+
+        Ok(Self {
+            source,
+            tracks: vec![Track::new(0, codec_params)],
+            cues: Vec::new(),
+            metadata: MetadataLog::default(),
+
+            bytes_per_frame: spec.bytes_per_frame(),
+            ts: 0,
+        })
+    }
+}
+
+impl FormatReader for RawPcmReader {
+    fn try_new(
+        _source: MediaSourceStream,
+        _options: &FormatOptions,
+    ) -> symphonia::core::errors::Result<Self> {
+        Err(SymphoniaError::Unsupported(
+            "raw pcm cannot be probed, it needs a declared spec",
+        ))
+    }
+
+    fn cues(&self) -> &[Cue] {
+        &self.cues
+    }
+
+    fn metadata(&mut self) -> Metadata<'_> {
+        self.metadata.metadata()
+    }
+
+    fn seek(&mut self, _mode: SeekMode, to: SeekTo) -> symphonia::core::errors::Result<SeekedTo> {
+        let ts = match to {
+            SeekTo::Time { time, .. } => {
+                let sample_rate = self.tracks[0].codec_params.sample_rate.unwrap_or(1);
+                TimeBase::new(1, sample_rate).calc_timestamp(time)
+            }
+            SeekTo::TimeStamp { ts, .. } => ts,
+        };
+
+        if ts >= self.ts {
+            self.source
+                .ignore_bytes((ts - self.ts) * self.bytes_per_frame)?;
+        } else if self.source.is_seekable() {
+            self.source
+                .seek(SeekFrom::Start(ts * self.bytes_per_frame))?;
+        } else {
+            return Err(SymphoniaError::SeekError(
+                symphonia::core::errors::SeekErrorKind::ForwardOnly,
+            ));
+        }
+        self.ts = ts;
+
+        Ok(SeekedTo {
+            track_id: 0,
+            required_ts: ts,
+            actual_ts: ts,
+        })
+    }
+
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn next_packet(&mut self) -> symphonia::core::errors::Result<Packet> {
+        // Fill a whole packet if possible, since pipes return short reads.
+        let mut buffer = vec![0; (FRAMES_PER_PACKET * self.bytes_per_frame) as usize];
+        let mut len = 0;
+        while len < buffer.len() {
+            match self.source.read(&mut buffer[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // Drop a trailing partial frame.
+        let frames = len as u64 / self.bytes_per_frame;
+        if frames == 0 {
+            return Err(SymphoniaError::IoError(
+                std::io::ErrorKind::UnexpectedEof.into(),
+            ));
+        }
+        buffer.truncate((frames * self.bytes_per_frame) as usize);
+
+        let packet = Packet::new_from_boxed_slice(0, self.ts, frames, buffer.into_boxed_slice());
+        self.ts += frames;
+
+        Ok(packet)
+    }
+
+    fn into_inner(self: Box<Self>) -> MediaSourceStream {
+        self.source
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decoder::DecodeOptions;
+    use crate::decoder::Decoder;
+    use std::time::Duration;
+    use symphonia::core::io::ReadOnlySource;
+
+    fn make_raw_decoder(data: Vec<u8>, spec: &RawPcmSpec) -> Decoder {
+        let media_source = Box::new(ReadOnlySource::new(std::io::Cursor::new(data)));
+        Decoder::new_raw(media_source, spec, DecodeOptions::default())
+            .expect("failed to make decoder")
+    }
+
+    #[test]
+    fn s16le_stereo() {
+        let spec = RawPcmSpec {
+            sample_format: RawSampleFormat::S16Le,
+            sample_rate: 8000,
+            num_channels: 2,
+        };
+
+        // 3000 frames, plus a trailing partial frame.
+        let mut data = Vec::new();
+        for i in 0..3000_i16 {
+            data.extend_from_slice(&i.to_le_bytes());
+            data.extend_from_slice(&(-i).to_le_bytes());
+        }
+        data.push(0);
+
+        let mut decoder = make_raw_decoder(data, &spec);
+        let mut samples = Vec::new();
+        while let Some(chunk) = decoder.next_chunk().expect("failed to decode") {
+            assert_eq!(chunk.spec.rate, 8000);
+            assert_eq!(chunk.spec.channels.count(), 2);
+            samples.extend(chunk.samples);
+        }
+
+        assert_eq!(samples.len(), 6000);
+        assert_eq!(samples[2], 1.0 / 32768.0);
+        assert_eq!(samples[3], -1.0 / 32768.0);
+    }
+
+    #[test]
+    fn seek_forward_only() {
+        let spec = RawPcmSpec {
+            sample_format: RawSampleFormat::U8,
+            sample_rate: 1000,
+            num_channels: 1,
+        };
+
+        let mut decoder = make_raw_decoder(vec![128; 2000], &spec);
+        decoder
+            .seek(Duration::from_millis(500))
+            .expect("failed to seek");
+        assert!(decoder.seek(Duration::from_millis(100)).is_err());
+
+        let mut num_frames = 0;
+        while let Some(chunk) = decoder.next_chunk().expect("failed to decode") {
+            num_frames += chunk.frames();
+        }
+        assert_eq!(num_frames, 1500);
+    }
+
+    #[test]
+    fn invalid_spec() {
+        for (sample_rate, num_channels) in [(0, 2), (8000, 0), (8000, 33)].iter() {
+            let spec = RawPcmSpec {
+                sample_format: RawSampleFormat::S16Le,
+                sample_rate: *sample_rate,
+                num_channels: *num_channels,
+            };
+            assert!(spec.validate().is_err());
+
+            let media_source = Box::new(ReadOnlySource::new(std::io::Cursor::new(vec![0; 64])));
+            assert!(Decoder::new_raw(media_source, &spec, DecodeOptions::default()).is_err());
+        }
+    }
+}
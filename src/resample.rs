@@ -0,0 +1,528 @@
+#[cfg(not(feature = "libsamplerate"))]
+use crate::sinc::SincResampler;
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The frequency of the test tone used to measure resampling quality
+const TEST_TONE_FREQUENCY: f64 = 997.0;
+
+/// The length of the test tone used to measure resampling quality, in seconds
+const TEST_TONE_SECONDS: u32 = 1;
+
+/// The most input frames a [`StreamingResampler`] resamples at once.
+///
+/// This bounds how far resampling runs ahead of the audio that was asked for.
+const BLOCK_FRAMES: usize = 512;
+
+/// How to convert audio to a device's sample rate
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResampleQuality {
+    /// The best sinc interpolator
+    #[default]
+    SincBest,
+
+    /// A medium sinc interpolator
+    SincMedium,
+
+    /// The fastest sinc interpolator
+    SincFastest,
+
+    /// A zero order hold interpolator
+    ZeroOrderHold,
+
+    /// A linear interpolator
+    Linear,
+
+    /// Do not resample, and fail if the input's sample rate does not match the device's
+    Native,
+}
+
+impl ResampleQuality {
+    /// All qualities, from best to worst
+    pub const ALL: &'static [Self] = &[
+        Self::SincBest,
+        Self::SincMedium,
+        Self::SincFastest,
+        Self::Linear,
+        Self::ZeroOrderHold,
+        Self::Native,
+    ];
+
+    /// Get the libsamplerate converter for this quality.
+    ///
+    /// Returns `None` if this quality does not resample.
+    #[cfg(feature = "libsamplerate")]
+    pub fn converter_type(self) -> Option<samplerate::ConverterType> {
+        match self {
+            Self::SincBest => Some(samplerate::ConverterType::SincBestQuality),
+            Self::SincMedium => Some(samplerate::ConverterType::SincMediumQuality),
+            Self::SincFastest => Some(samplerate::ConverterType::SincFastest),
+            Self::ZeroOrderHold => Some(samplerate::ConverterType::ZeroOrderHold),
+            Self::Linear => Some(samplerate::ConverterType::Linear),
+            Self::Native => None,
+        }
+    }
+
+    /// Get the name of this quality, as used on the command line and in config files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::SincBest => "sinc-best",
+            Self::SincMedium => "sinc-medium",
+            Self::SincFastest => "sinc-fastest",
+            Self::ZeroOrderHold => "zero-order-hold",
+            Self::Linear => "linear",
+            Self::Native => "native",
+        }
+    }
+}
+
+impl FromStr for ResampleQuality {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|quality| quality.as_str() == input)
+            .with_context(|| format!("invalid resample quality '{}'", input))
+    }
+}
+
+impl fmt::Display for ResampleQuality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// The resampler implementation, libsamplerate or the pure-Rust one,
+/// picked by the `libsamplerate` feature.
+struct Backend {
+    #[cfg(feature = "libsamplerate")]
+    resampler: samplerate::Samplerate,
+
+    #[cfg(not(feature = "libsamplerate"))]
+    resampler: SincResampler,
+}
+
+// SAFETY: A libsamplerate state is not tied to the thread that made it,
+// and `&mut self` or ownership is needed to use it, so it is never used from two threads at once.
+#[cfg(feature = "libsamplerate")]
+unsafe impl Send for Backend {}
+
+impl Backend {
+    fn new(
+        quality: ResampleQuality,
+        from_rate: u32,
+        to_rate: u32,
+        num_channels: usize,
+    ) -> anyhow::Result<Self> {
+        if quality == ResampleQuality::Native {
+            anyhow::bail!(
+                "the input's sample rate of {} Hz does not match the device's {} Hz, and resampling is disabled",
+                from_rate,
+                to_rate
+            );
+        }
+
+        #[cfg(feature = "libsamplerate")]
+        let resampler = samplerate::Samplerate::new(
+            quality.converter_type().expect("missing converter"),
+            from_rate,
+            to_rate,
+            num_channels,
+        );
+
+        #[cfg(not(feature = "libsamplerate"))]
+        let resampler = SincResampler::new(quality, from_rate, to_rate, num_channels);
+
+        Ok(Self {
+            resampler: resampler.context("failed to create resampler")?,
+        })
+    }
+
+    fn process(&mut self, input: &[f32]) -> anyhow::Result<Vec<f32>> {
+        self.resampler
+            .process(input)
+            .context("failed to resample audio")
+    }
+
+    fn process_last(&mut self, input: &[f32]) -> anyhow::Result<Vec<f32>> {
+        self.resampler
+            .process_last(input)
+            .context("failed to flush resampler")
+    }
+
+    fn reset(&mut self) -> anyhow::Result<()> {
+        #[cfg(feature = "libsamplerate")]
+        self.resampler
+            .reset()
+            .context("failed to reset resampler")?;
+
+        #[cfg(not(feature = "libsamplerate"))]
+        self.resampler.reset();
+
+        Ok(())
+    }
+}
+
+/// Resample a whole buffer of interleaved audio at once.
+///
+/// # Errors
+/// Returns an error if the quality does not resample, or resampling failed.
+pub fn convert(
+    quality: ResampleQuality,
+    from_rate: u32,
+    to_rate: u32,
+    num_channels: usize,
+    input: &[f32],
+) -> anyhow::Result<Vec<f32>> {
+    Backend::new(quality, from_rate, to_rate, num_channels)?.process_last(input)
+}
+
+/// A resampler for audio that arrives and is played in chunks.
+///
+/// Input is queued as it arrives, and only resampled a block at a time as output is needed.
+/// The resampler's state is kept between blocks, so chunk boundaries are seamless.
+pub struct StreamingResampler {
+    resampler: Backend,
+    num_channels: usize,
+
+    input: VecDeque<f32>,
+    output: VecDeque<f32>,
+    is_finished: bool,
+    is_flushed: bool,
+}
+
+impl StreamingResampler {
+    /// Make a new [`StreamingResampler`] for interleaved audio.
+    ///
+    /// # Errors
+    /// Returns an error if the quality does not resample, or the resampler could not be made.
+    pub fn new(
+        quality: ResampleQuality,
+        from_rate: u32,
+        to_rate: u32,
+        num_channels: usize,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            resampler: Backend::new(quality, from_rate, to_rate, num_channels)?,
+            num_channels,
+
+            input: VecDeque::new(),
+            output: VecDeque::new(),
+            is_finished: false,
+            is_flushed: false,
+        })
+    }
+
+    /// Get the number of interleaved channels.
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Get the number of input frames queued but not yet resampled.
+    pub fn num_queued_frames(&self) -> usize {
+        self.input.len() / self.num_channels
+    }
+
+    /// Queue interleaved input samples.
+    ///
+    /// # Panics
+    /// Panics if the input was already finished.
+    pub fn push(&mut self, samples: &[f32]) {
+        assert!(!self.is_finished, "input pushed after it was finished");
+        self.input.extend(samples);
+    }
+
+    /// Mark the end of the input, so that the audio left in the resampler is flushed.
+    pub fn finish(&mut self) {
+        self.is_finished = true;
+    }
+
+    /// Check whether all audio was resampled and read, after the input was finished.
+    pub fn is_drained(&self) -> bool {
+        self.is_flushed && self.output.is_empty()
+    }
+
+    /// Resample the next block of queued input.
+    ///
+    /// Returns `None` if more input is needed, or if the resampler was flushed.
+    /// A block may resample to no samples while the resampler fills its history.
+    ///
+    /// # Errors
+    /// Returns an error if resampling failed.
+    pub fn process_block(&mut self) -> anyhow::Result<Option<Vec<f32>>> {
+        if !self.input.is_empty() {
+            let len = self.input.len().min(BLOCK_FRAMES * self.num_channels);
+            let block: Vec<f32> = self.input.drain(..len).collect();
+            return self.resampler.process(&block).map(Some);
+        }
+
+        if self.is_finished && !self.is_flushed {
+            self.is_flushed = true;
+            return self.resampler.process_last(&[]).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    /// Fill a buffer with interleaved output samples.
+    ///
+    /// Only enough input is resampled to fill the buffer,
+    /// rounded up to a block, and the rest stays queued.
+    /// Returns the number of frames written,
+    /// which is less than the buffer holds if more input is needed or the resampler was drained.
+    ///
+    /// # Errors
+    /// Returns an error if resampling failed.
+    pub fn read(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
+        while self.output.len() < buffer.len() {
+            match self.process_block()? {
+                Some(samples) => self.output.extend(samples),
+                None => break,
+            }
+        }
+
+        let len = buffer.len().min(self.output.len()) / self.num_channels * self.num_channels;
+        for (sample, output) in buffer.iter_mut().zip(self.output.drain(..len)) {
+            *sample = output;
+        }
+
+        Ok(len / self.num_channels)
+    }
+
+    /// Drop all queued audio and clear the resampler's history, as after a seek.
+    ///
+    /// # Errors
+    /// Returns an error if the resampler could not be reset.
+    pub fn reset(&mut self) -> anyhow::Result<()> {
+        self.resampler.reset()?;
+        self.input.clear();
+        self.output.clear();
+        self.is_finished = false;
+        self.is_flushed = false;
+
+        Ok(())
+    }
+}
+
+/// How fast and how accurate a resample quality is
+#[derive(Debug, Clone, Copy)]
+pub struct QualityReport {
+    /// The quality that was measured
+    pub quality: ResampleQuality,
+
+    /// The time taken to resample one second of audio
+    pub elapsed: Duration,
+
+    /// The estimated signal to noise ratio of the resampled audio, in dB
+    pub snr: f64,
+}
+
+impl fmt::Display for QualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:.2} ms per second of audio, SNR {:.1} dB",
+            self.quality,
+            self.elapsed.as_secs_f64() * 1000.0 / f64::from(TEST_TONE_SECONDS),
+            self.snr
+        )
+    }
+}
+
+/// Measure the speed and accuracy of a resample quality.
+///
+/// A mono test tone is resampled between the given rates,
+/// and the SNR is estimated from how far the output is from an ideal tone.
+///
+/// Returns `None` for qualities that do not resample.
+///
+/// # Errors
+/// Returns an error if resampling failed.
+pub fn measure_quality(
+    quality: ResampleQuality,
+    from_rate: u32,
+    to_rate: u32,
+) -> anyhow::Result<Option<QualityReport>> {
+    if quality == ResampleQuality::Native {
+        return Ok(None);
+    }
+
+    let input = make_tone(
+        TEST_TONE_FREQUENCY,
+        from_rate,
+        from_rate * TEST_TONE_SECONDS,
+    );
+
+    let start = Instant::now();
+    let output =
+        convert(quality, from_rate, to_rate, 1, &input).context("failed to resample test tone")?;
+    let elapsed = start.elapsed();
+
+    // Skip the edges, where the resampler has no history.
+    let edge = output.len() / 10;
+    let snr = estimate_snr(
+        &output[edge..output.len() - edge],
+        TEST_TONE_FREQUENCY,
+        to_rate,
+    );
+
+    Ok(Some(QualityReport {
+        quality,
+        elapsed,
+        snr,
+    }))
+}
+
+/// Make a full scale sine tone.
+fn make_tone(frequency: f64, rate: u32, num_frames: u32) -> Vec<f32> {
+    (0..num_frames)
+        .map(|i| {
+            let t = f64::from(i) / f64::from(rate);
+            (2.0 * std::f64::consts::PI * frequency * t).sin() as f32
+        })
+        .collect()
+}
+
+/// Estimate the SNR of a tone, in dB.
+///
+/// The ideal tone is fitted to the samples by least squares,
+/// so the estimate does not depend on the phase or delay of the samples.
+pub(crate) fn estimate_snr(samples: &[f32], frequency: f64, rate: u32) -> f64 {
+    let omega = 2.0 * std::f64::consts::PI * frequency / f64::from(rate);
+
+    let mut sin_sum = 0.0;
+    let mut cos_sum = 0.0;
+    for (i, sample) in samples.iter().enumerate() {
+        let phase = omega * i as f64;
+        sin_sum += f64::from(*sample) * phase.sin();
+        cos_sum += f64::from(*sample) * phase.cos();
+    }
+    let sin_amplitude = 2.0 * sin_sum / samples.len() as f64;
+    let cos_amplitude = 2.0 * cos_sum / samples.len() as f64;
+
+    let mut signal_power = 0.0;
+    let mut noise_power = 0.0;
+    for (i, sample) in samples.iter().enumerate() {
+        let phase = omega * i as f64;
+        let ideal = sin_amplitude * phase.sin() + cos_amplitude * phase.cos();
+        signal_power += ideal * ideal;
+        noise_power += (f64::from(*sample) - ideal).powi(2);
+    }
+
+    10.0 * (signal_power / noise_power.max(f64::MIN_POSITIVE)).log10()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_quality() {
+        for quality in ResampleQuality::ALL.iter() {
+            assert_eq!(
+                quality.as_str().parse::<ResampleQuality>().ok(),
+                Some(*quality)
+            );
+        }
+        assert!("best".parse::<ResampleQuality>().is_err());
+    }
+
+    fn make_reference(tone: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+        let mut resampler = Backend::new(ResampleQuality::SincBest, from_rate, to_rate, 2)
+            .expect("failed to create resampler");
+        let mut reference = resampler.process(tone).expect("failed to resample");
+        reference.extend(resampler.process_last(&[]).expect("failed to flush"));
+        reference
+    }
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        let tone: Vec<f32> = make_tone(TEST_TONE_FREQUENCY, 44100, 44100)
+            .into_iter()
+            .flat_map(|sample| vec![sample, -sample])
+            .collect();
+        let reference = make_reference(&tone, 44100, 48000);
+
+        let mut resampler = StreamingResampler::new(ResampleQuality::SincBest, 44100, 48000, 2)
+            .expect("failed to create resampler");
+
+        // Push and read in uneven sizes, like decoded packets and device periods.
+        let mut output = Vec::new();
+        let mut buffer = vec![0.0; 2 * 441];
+        let mut offset = 0;
+        let mut i = 0;
+        while offset < tone.len() {
+            let len = (2 * (1000 + 37 * (i % 7))).min(tone.len() - offset);
+            resampler.push(&tone[offset..offset + len]);
+            offset += len;
+
+            let len = 2 * (100 + 53 * (i % 5));
+            let num_frames = resampler.read(&mut buffer[..len]).expect("failed to read");
+            output.extend_from_slice(&buffer[..2 * num_frames]);
+            i += 1;
+        }
+        resampler.finish();
+        while !resampler.is_drained() {
+            let num_frames = resampler.read(&mut buffer).expect("failed to read");
+            output.extend_from_slice(&buffer[..2 * num_frames]);
+        }
+
+        assert_eq!(output.len(), reference.len());
+        for (sample, expected) in output.iter().zip(reference.iter()) {
+            assert!((sample - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn streaming_is_on_demand() {
+        let mut resampler = StreamingResampler::new(ResampleQuality::SincBest, 48000, 44100, 1)
+            .expect("failed to create resampler");
+        resampler.push(&make_tone(TEST_TONE_FREQUENCY, 48000, 48000));
+
+        // Reading a device period only resamples a few blocks of the second that was queued.
+        let mut buffer = vec![0.0; 441];
+        let num_frames = resampler.read(&mut buffer).expect("failed to read");
+        assert_eq!(num_frames, 441);
+        assert!(resampler.num_queued_frames() >= 48000 - 4 * BLOCK_FRAMES);
+
+        // Nothing is flushed until the input is finished.
+        while resampler.num_queued_frames() != 0 {
+            resampler.read(&mut buffer).expect("failed to read");
+        }
+        while resampler.read(&mut buffer).expect("failed to read") != 0 {}
+        assert!(!resampler.is_drained());
+        resampler.finish();
+        while resampler.read(&mut buffer).expect("failed to read") != 0 {}
+        assert!(resampler.is_drained());
+    }
+
+    #[test]
+    fn snr() {
+        let rate = 48000;
+        let tone = make_tone(TEST_TONE_FREQUENCY, rate, rate);
+
+        // Rounding to f32 alone keeps the SNR well above 100 dB.
+        assert!(estimate_snr(&tone, TEST_TONE_FREQUENCY, rate) > 100.0);
+
+        // Noise at -40 dB relative to the tone's power.
+        let noise_amplitude = 0.01;
+        let noisy: Vec<f32> = tone
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| {
+                let noise = if i % 2 == 0 { 1.0 } else { -1.0 };
+                sample + noise * noise_amplitude * std::f32::consts::FRAC_1_SQRT_2
+            })
+            .collect();
+        let snr = estimate_snr(&noisy, TEST_TONE_FREQUENCY, rate);
+        assert!((snr - 40.0).abs() < 0.5, "snr was {}", snr);
+    }
+}
@@ -0,0 +1,138 @@
+use symphonia::core::sample::i24;
+use symphonia::core::sample::u24;
+
+/// A sample that can be converted into the internal f32 representation.
+///
+/// Signed integer samples are scaled so that their minimum value maps to -1.0.
+/// Unsigned integer samples are centered on their midpoint first, so the midpoint maps to 0.0.
+/// Float samples are passed through.
+pub trait IntoF32Sample: Copy {
+    /// Convert this sample into an f32 sample.
+    fn into_f32_sample(self) -> f32;
+}
+
+impl IntoF32Sample for u8 {
+    fn into_f32_sample(self) -> f32 {
+        (f32::from(self) - 128.0) / 128.0
+    }
+}
+
+impl IntoF32Sample for u16 {
+    fn into_f32_sample(self) -> f32 {
+        (f32::from(self) - 32_768.0) / 32_768.0
+    }
+}
+
+impl IntoF32Sample for u24 {
+    fn into_f32_sample(self) -> f32 {
+        ((f64::from(self.inner()) - 8_388_608.0) / 8_388_608.0) as f32
+    }
+}
+
+impl IntoF32Sample for u32 {
+    fn into_f32_sample(self) -> f32 {
+        ((f64::from(self) - 2_147_483_648.0) / 2_147_483_648.0) as f32
+    }
+}
+
+impl IntoF32Sample for i8 {
+    fn into_f32_sample(self) -> f32 {
+        f32::from(self) / 128.0
+    }
+}
+
+impl IntoF32Sample for i16 {
+    fn into_f32_sample(self) -> f32 {
+        f32::from(self) / 32_768.0
+    }
+}
+
+impl IntoF32Sample for i24 {
+    fn into_f32_sample(self) -> f32 {
+        (f64::from(self.inner()) / 8_388_608.0) as f32
+    }
+}
+
+impl IntoF32Sample for i32 {
+    fn into_f32_sample(self) -> f32 {
+        (f64::from(self) / 2_147_483_648.0) as f32
+    }
+}
+
+impl IntoF32Sample for f32 {
+    fn into_f32_sample(self) -> f32 {
+        self
+    }
+}
+
+impl IntoF32Sample for f64 {
+    fn into_f32_sample(self) -> f32 {
+        self as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unsigned() {
+        assert_eq!(u8::MIN.into_f32_sample(), -1.0);
+        assert_eq!(128_u8.into_f32_sample(), 0.0);
+        assert_eq!(u8::MAX.into_f32_sample(), 127.0 / 128.0);
+
+        assert_eq!(u16::MIN.into_f32_sample(), -1.0);
+        assert_eq!(32_768_u16.into_f32_sample(), 0.0);
+        assert_eq!(u16::MAX.into_f32_sample(), 32_767.0 / 32_768.0);
+
+        assert_eq!(u24::MIN.into_f32_sample(), -1.0);
+        assert_eq!(u24(8_388_608).into_f32_sample(), 0.0);
+        assert_eq!(
+            u24::MAX.into_f32_sample(),
+            (8_388_607.0_f64 / 8_388_608.0) as f32
+        );
+
+        assert_eq!(u32::MIN.into_f32_sample(), -1.0);
+        assert_eq!(2_147_483_648_u32.into_f32_sample(), 0.0);
+        assert_eq!(
+            u32::MAX.into_f32_sample(),
+            (2_147_483_647.0_f64 / 2_147_483_648.0) as f32
+        );
+    }
+
+    #[test]
+    fn signed() {
+        assert_eq!(i8::MIN.into_f32_sample(), -1.0);
+        assert_eq!(0_i8.into_f32_sample(), 0.0);
+        assert_eq!(i8::MAX.into_f32_sample(), 127.0 / 128.0);
+
+        assert_eq!(i16::MIN.into_f32_sample(), -1.0);
+        assert_eq!(0_i16.into_f32_sample(), 0.0);
+        assert_eq!(i16::MAX.into_f32_sample(), 32_767.0 / 32_768.0);
+
+        assert_eq!(i24::MIN.into_f32_sample(), -1.0);
+        assert_eq!(i24(0).into_f32_sample(), 0.0);
+        assert_eq!(
+            i24::MAX.into_f32_sample(),
+            (8_388_607.0_f64 / 8_388_608.0) as f32
+        );
+
+        assert_eq!(i32::MIN.into_f32_sample(), -1.0);
+        assert_eq!(0_i32.into_f32_sample(), 0.0);
+        assert_eq!(
+            i32::MAX.into_f32_sample(),
+            (2_147_483_647.0_f64 / 2_147_483_648.0) as f32
+        );
+    }
+
+    #[test]
+    fn float() {
+        assert_eq!((-1.0_f32).into_f32_sample(), -1.0);
+        assert_eq!(0.0_f32.into_f32_sample(), 0.0);
+        assert_eq!(1.0_f32.into_f32_sample(), 1.0);
+
+        assert_eq!((-1.0_f64).into_f32_sample(), -1.0);
+        assert_eq!(0.0_f64.into_f32_sample(), 0.0);
+        assert_eq!(1.0_f64.into_f32_sample(), 1.0);
+    }
+}
@@ -0,0 +1,435 @@
+use crate::clock::Clock;
+use crate::mix::default_layout;
+use crate::null::NullSink;
+use crate::pcm::PcmFormat;
+use crate::raw::RawSampleFormat;
+use crate::sink::AudioBackend;
+use crate::sink::AudioSink;
+use crate::sink::DeviceInfo;
+use crate::sink::SinkFormat;
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use symphonia::core::audio::Channels;
+
+/// The number of periods a simulated device plays every second, by default
+const DEFAULT_PERIODS_PER_SECOND: u32 = 100;
+
+/// The state of a simulated device, like WASAPI's DEVICE_STATE_*
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SimulatedDeviceState {
+    /// The device can play audio
+    #[default]
+    Active,
+
+    /// The device was disabled by the user
+    Disabled,
+
+    /// The device is not present, like a removed USB device
+    NotPresent,
+
+    /// The device's jack is unplugged
+    Unplugged,
+}
+
+/// The format a simulated device mixes in
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatedMixFormat {
+    /// The sample rate
+    pub sample_rate: u32,
+
+    /// The number of channels
+    pub num_channels: usize,
+
+    /// The speaker positions of the channels, as a WAVE channel mask.
+    ///
+    /// Defaults to the standard layout for the number of channels.
+    #[serde(default)]
+    pub channel_mask: Option<u32>,
+
+    /// The format of each sample, like "f32le" or "s16le"
+    pub sample_format: RawSampleFormat,
+
+    /// The number of bits of each sample that are used.
+    ///
+    /// Defaults to all of them.
+    #[serde(default)]
+    pub valid_bits_per_sample: Option<u16>,
+}
+
+/// Failures to inject into a simulated device
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulatedFailures {
+    /// Opening the device fails with this message, like a device that is in use
+    pub open: Option<String>,
+
+    /// The device is disconnected after playing this many frames
+    pub disconnect_after_frames: Option<u64>,
+}
+
+/// A simulated audio device
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatedDevice {
+    /// The device's unique ID
+    pub id: String,
+
+    /// The device's friendly name.
+    ///
+    /// Without one, the name is made up, like when WASAPI fails to read it.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// The device's state. Only active devices are listed.
+    #[serde(default)]
+    pub state: SimulatedDeviceState,
+
+    /// The format the device mixes in
+    pub mix_format: SimulatedMixFormat,
+
+    /// The number of frames played every period.
+    ///
+    /// Defaults to 10 ms.
+    #[serde(default)]
+    pub period_frames: Option<u32>,
+
+    /// The number of frames the buffer holds.
+    ///
+    /// Defaults to 2 periods.
+    #[serde(default)]
+    pub buffer_frames: Option<u32>,
+
+    /// Failures to inject
+    #[serde(default)]
+    pub failures: SimulatedFailures,
+}
+
+impl SimulatedDevice {
+    /// Get the format the device plays.
+    fn format(&self) -> anyhow::Result<SinkFormat> {
+        let mix_format = &self.mix_format;
+        let packed = PcmFormat::packed(mix_format.sample_format, mix_format.num_channels)?;
+        let pcm_format = match mix_format.valid_bits_per_sample {
+            Some(valid_bits_per_sample) => PcmFormat::new(
+                packed.encoding(),
+                packed.bits_per_sample(),
+                valid_bits_per_sample,
+                packed.num_channels(),
+                packed.block_align(),
+            )?,
+            None => packed,
+        };
+        let channel_layout = mix_format
+            .channel_mask
+            .map(Channels::from_bits_truncate)
+            .unwrap_or_else(|| default_layout(mix_format.num_channels));
+
+        Ok(SinkFormat {
+            sample_rate: mix_format.sample_rate,
+            channel_layout,
+            pcm_format,
+        })
+    }
+}
+
+/// A description of simulated audio devices, loaded from a TOML or JSON file
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulatedDevices {
+    /// Listing the devices fails with this message
+    pub enumerate_error: Option<String>,
+
+    /// The devices, in the order they are listed
+    pub devices: Vec<SimulatedDevice>,
+}
+
+impl SimulatedDevices {
+    /// Load a description of devices.
+    ///
+    /// Files ending in ".json" are parsed as JSON, and everything else as TOML.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be read or is invalid.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read devices '{}'", path.display()))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        if is_json {
+            serde_json::from_str(&data)
+                .with_context(|| format!("failed to parse devices '{}'", path.display()))
+        } else {
+            toml::from_str(&data)
+                .with_context(|| format!("failed to parse devices '{}'", path.display()))
+        }
+    }
+}
+
+/// A backend of simulated devices, which play nothing at the pace of a clock.
+///
+/// This runs anywhere, so the code that picks and drives devices can be tested without any.
+#[derive(Debug, Clone)]
+pub struct SimulatedBackend<C> {
+    devices: Arc<SimulatedDevices>,
+    clock: C,
+}
+
+impl<C> SimulatedBackend<C>
+where
+    C: Clock + Clone,
+{
+    /// Make a new [`SimulatedBackend`], where every device plays by a clock.
+    pub fn new(devices: Arc<SimulatedDevices>, clock: C) -> Self {
+        Self { devices, clock }
+    }
+
+    /// Get the active devices.
+    fn active_devices(&self) -> impl Iterator<Item = &SimulatedDevice> {
+        self.devices
+            .devices
+            .iter()
+            .filter(|device| device.state == SimulatedDeviceState::Active)
+    }
+}
+
+impl<C> AudioBackend for SimulatedBackend<C>
+where
+    C: Clock + Clone,
+{
+    type Sink = SimulatedSink<C>;
+
+    fn devices(&self) -> anyhow::Result<Vec<DeviceInfo>> {
+        if let Some(error) = self.devices.enumerate_error.as_deref() {
+            return Err(anyhow::anyhow!("{}", error))
+                .context("failed to enumerate audio endpoints");
+        }
+
+        Ok(self
+            .active_devices()
+            .enumerate()
+            .map(|(index, device)| DeviceInfo {
+                name: device
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("Audio device {}", index)),
+            })
+            .collect())
+    }
+
+    fn open(&self, index: usize) -> anyhow::Result<SimulatedSink<C>> {
+        let device = self
+            .active_devices()
+            .nth(index)
+            .with_context(|| format!("missing audio device {}", index))?;
+        if let Some(error) = device.failures.open.as_deref() {
+            return Err(anyhow::anyhow!("{}", error))
+                .with_context(|| format!("failed to open '{}'", device.id));
+        }
+
+        let format = device.format()?;
+        let period_frames = device
+            .period_frames
+            .unwrap_or(format.sample_rate / DEFAULT_PERIODS_PER_SECOND);
+        let buffer_frames = device.buffer_frames.unwrap_or(period_frames * 2);
+        let sink = NullSink::new(format, buffer_frames, period_frames, self.clock.clone())
+            .with_context(|| format!("invalid device '{}'", device.id))?;
+
+        Ok(SimulatedSink {
+            sink,
+            disconnect_after_frames: device.failures.disconnect_after_frames,
+        })
+    }
+}
+
+/// An open simulated device
+#[derive(Debug)]
+pub struct SimulatedSink<C> {
+    sink: NullSink<C>,
+    disconnect_after_frames: Option<u64>,
+}
+
+impl<C> SimulatedSink<C>
+where
+    C: Clock,
+{
+    /// Get the sink that records what the device played.
+    pub fn null_sink(&self) -> &NullSink<C> {
+        &self.sink
+    }
+
+    /// Fail if the device was disconnected.
+    fn check_connected(&self) -> anyhow::Result<()> {
+        let num_frames_played = self.sink.playhead().num_frames_played();
+        match self.disconnect_after_frames {
+            Some(num_frames) if num_frames_played >= num_frames => {
+                anyhow::bail!("the device was disconnected")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<C> AudioSink for SimulatedSink<C>
+where
+    C: Clock,
+{
+    fn format(&self) -> SinkFormat {
+        self.sink.format()
+    }
+
+    fn buffer_frames(&self) -> u32 {
+        self.sink.buffer_frames()
+    }
+
+    fn wait(&mut self) -> anyhow::Result<u32> {
+        let num_free_frames = self.sink.wait()?;
+        self.check_connected()?;
+        Ok(num_free_frames)
+    }
+
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.check_connected()?;
+        self.sink.write(data)
+    }
+
+    fn start(&mut self) -> anyhow::Result<()> {
+        self.check_connected()?;
+        self.sink.start()
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.sink.stop()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::pcm::PcmEncoding;
+
+    const DEVICES_TOML: &str = r#"
+        [[devices]]
+        id = "{0.0.0.00000000}.{speakers}"
+        name = "Speakers"
+        mix_format = { sample_rate = 48000, num_channels = 2, sample_format = "f32le" }
+
+        [[devices]]
+        id = "{0.0.0.00000000}.{hdmi}"
+        state = "unplugged"
+        mix_format = { sample_rate = 48000, num_channels = 8, sample_format = "f32le" }
+
+        [[devices]]
+        id = "{0.0.0.00000000}.{usb}"
+        period_frames = 441
+        buffer_frames = 1764
+        failures = { disconnect_after_frames = 44100 }
+
+        [devices.mix_format]
+        sample_rate = 44100
+        num_channels = 6
+        channel_mask = 0x60F
+        sample_format = "s32le"
+        valid_bits_per_sample = 24
+    "#;
+
+    fn backend() -> SimulatedBackend<VirtualClock> {
+        let devices = toml::from_str(DEVICES_TOML).expect("failed to parse devices");
+        SimulatedBackend::new(Arc::new(devices), VirtualClock::new())
+    }
+
+    #[test]
+    fn lists_active_devices() {
+        let devices = backend().devices().expect("failed to list devices");
+        assert_eq!(
+            devices,
+            [
+                DeviceInfo {
+                    name: "Speakers".into()
+                },
+                DeviceInfo {
+                    name: "Audio device 1".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn opens_devices_in_their_mix_format() {
+        let backend = backend();
+
+        let speakers = backend.open(0).expect("failed to open");
+        assert_eq!(speakers.format().pcm_format, PcmFormat::float32(2).unwrap());
+        assert_eq!(speakers.buffer_frames(), 960);
+
+        let usb = backend.open(1).expect("failed to open");
+        let format = usb.format();
+        assert_eq!(format.sample_rate, 44100);
+        assert_eq!(format.channel_layout.bits(), 0x60F);
+        assert_eq!(
+            format.pcm_format,
+            PcmFormat::new(PcmEncoding::Int, 32, 24, 6, 24).unwrap()
+        );
+        assert_eq!(usb.buffer_frames(), 1764);
+
+        assert!(backend.open(2).is_err());
+    }
+
+    #[test]
+    fn injects_failures() {
+        let mut devices: SimulatedDevices =
+            toml::from_str(DEVICES_TOML).expect("failed to parse devices");
+        devices.devices[0].failures.open = Some("the device is in use".into());
+        let backend = SimulatedBackend::new(Arc::new(devices.clone()), VirtualClock::new());
+        let error = backend.open(0).expect_err("opened a device in use");
+        assert!(format!("{:?}", error).contains("the device is in use"));
+
+        // The USB device is unplugged after a second.
+        let mut usb = backend.open(1).expect("failed to open");
+        let silence = vec![0; usb.buffer_frames() as usize * 24];
+        usb.write(&silence).expect("failed to write");
+        usb.start().expect("failed to start");
+        for _ in 0..99 {
+            let num_free_frames = usb.wait().expect("failed to wait");
+            usb.write(&silence[..num_free_frames as usize * 24])
+                .expect("failed to write");
+        }
+        assert!(usb.wait().is_err());
+        assert_eq!(usb.null_sink().playhead().num_frames_played(), 44100);
+
+        devices.enumerate_error = Some("the audio service is not running".into());
+        let backend = SimulatedBackend::new(Arc::new(devices), VirtualClock::new());
+        assert!(backend.devices().is_err());
+    }
+
+    #[test]
+    fn loads_json() {
+        let path =
+            std::env::temp_dir().join(format!("donacdum-devices-{}.json", std::process::id()));
+        let json = r#"{
+            "devices": [
+                {
+                    "id": "speakers",
+                    "mix_format": { "sample_rate": 48000, "num_channels": 2, "sample_format": "s16le" },
+                    "failures": { "open": "access denied" }
+                }
+            ]
+        }"#;
+        std::fs::write(&path, json).expect("failed to write devices");
+        let devices = SimulatedDevices::load(&path);
+        std::fs::remove_file(&path).expect("failed to remove devices");
+
+        let devices = devices.expect("failed to load devices");
+        assert_eq!(devices.devices.len(), 1);
+        assert_eq!(devices.devices[0].id, "speakers");
+        assert_eq!(
+            devices.devices[0].failures.open.as_deref(),
+            Some("access denied")
+        );
+    }
+}
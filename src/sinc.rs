@@ -0,0 +1,720 @@
+use crate::resample::ResampleQuality;
+use std::convert::TryFrom;
+
+/// The number of filter table entries between two zero crossings
+const TABLE_RESOLUTION: usize = 512;
+
+/// The most filter phases that are computed up front.
+///
+/// Rate pairs with more phases than this, like those used to correct clock drift,
+/// interpolate each output frame's filter between this many evenly spaced phases.
+const MAX_PHASES: u64 = 4096;
+
+/// The shape of a windowed-sinc low-pass filter
+#[derive(Debug, Clone, Copy)]
+struct FilterSpec {
+    /// The number of zero crossings on each side of the filter's centre
+    num_zero_crossings: usize,
+
+    /// The beta of the Kaiser window, which trades transition width for stopband attenuation
+    kaiser_beta: f64,
+
+    /// The cutoff, as a fraction of the lower of the two Nyquist frequencies
+    bandwidth: f64,
+}
+
+/// How output samples are interpolated from the input
+enum Kernel {
+    ZeroOrderHold,
+    Linear,
+    Sinc {
+        /// The filter from its centre to its last zero crossing, tabulated
+        table: Vec<f64>,
+        num_zero_crossings: usize,
+
+        /// The cutoff, as a fraction of the input's Nyquist frequency
+        cutoff: f64,
+
+        /// The normalized filter taps for evenly spaced fractions of an input frame.
+        ///
+        /// There is one for every output phase, or [`MAX_PHASES`] + 1 to interpolate between.
+        phases: Vec<Vec<f32>>,
+    },
+}
+
+impl Kernel {
+    /// Get the number of input frames needed on each side of an output frame.
+    fn half_width(&self) -> usize {
+        match self {
+            Self::ZeroOrderHold | Self::Linear => 1,
+            Self::Sinc {
+                num_zero_crossings,
+                cutoff,
+                ..
+            } => (*num_zero_crossings as f64 / cutoff).ceil() as usize + 1,
+        }
+    }
+
+    /// Get the sinc filter taps for an output frame a fraction of an input frame past one.
+    ///
+    /// The taps are normalized, so that DC passes through exactly.
+    fn sinc_weights(
+        table: &[f64],
+        num_zero_crossings: usize,
+        cutoff: f64,
+        half_width: usize,
+        fraction: f64,
+        weights: &mut Vec<f32>,
+    ) {
+        let tap_weight = |tap: usize| {
+            let distance = fraction + half_width as f64 - 1.0 - tap as f64;
+            let index = (distance * cutoff).abs() * TABLE_RESOLUTION as f64;
+            let whole = index as usize;
+            if whole >= num_zero_crossings * TABLE_RESOLUTION {
+                return 0.0;
+            }
+            let fraction = index - whole as f64;
+            table[whole] + (table[whole + 1] - table[whole]) * fraction
+        };
+
+        let sum: f64 = (0..2 * half_width).map(tap_weight).sum();
+        weights.clear();
+        weights.extend((0..2 * half_width).map(|tap| (tap_weight(tap) / sum) as f32));
+    }
+}
+
+/// A pure-Rust resampler for interleaved audio, with the same interface as libsamplerate's.
+///
+/// The sinc qualities use a Kaiser-windowed sinc filter, the others interpolate like libsamplerate.
+/// Each output frame is aligned with the input,
+/// so output frame `n` is the input interpolated at `n * from_rate / to_rate` frames.
+pub struct SincResampler {
+    kernel: Kernel,
+    from_rate: u64,
+    to_rate: u64,
+    num_channels: usize,
+
+    /// Buffered input, including the history before the next output frame
+    input: Vec<f32>,
+
+    /// The buffered frame the next output frame is at or after
+    position: usize,
+
+    /// How far the next output frame is past `position`, in units of 1 / `to_rate` frames
+    fraction: u64,
+
+    weights: Vec<f32>,
+}
+
+impl SincResampler {
+    /// Make a new [`SincResampler`].
+    ///
+    /// # Errors
+    /// Returns an error if the quality does not resample, or the rates or channels are zero.
+    pub fn new(
+        quality: ResampleQuality,
+        from_rate: u32,
+        to_rate: u32,
+        num_channels: usize,
+    ) -> anyhow::Result<Self> {
+        if from_rate == 0 || to_rate == 0 {
+            anyhow::bail!("cannot resample from {} Hz to {} Hz", from_rate, to_rate);
+        }
+        if num_channels == 0 {
+            anyhow::bail!("cannot resample audio with no channels");
+        }
+
+        let spec = match quality {
+            ResampleQuality::SincBest => FilterSpec {
+                num_zero_crossings: 64,
+                kaiser_beta: 12.0,
+                bandwidth: 0.97,
+            },
+            ResampleQuality::SincMedium => FilterSpec {
+                num_zero_crossings: 24,
+                kaiser_beta: 9.0,
+                bandwidth: 0.92,
+            },
+            ResampleQuality::SincFastest => FilterSpec {
+                num_zero_crossings: 8,
+                kaiser_beta: 6.0,
+                bandwidth: 0.85,
+            },
+            ResampleQuality::ZeroOrderHold => {
+                return Ok(Self::with_kernel(
+                    Kernel::ZeroOrderHold,
+                    from_rate,
+                    to_rate,
+                    num_channels,
+                ))
+            }
+            ResampleQuality::Linear => {
+                return Ok(Self::with_kernel(
+                    Kernel::Linear,
+                    from_rate,
+                    to_rate,
+                    num_channels,
+                ))
+            }
+            ResampleQuality::Native => anyhow::bail!("the {} quality does not resample", quality),
+        };
+
+        // Downsampling lowers the cutoff to the output's Nyquist frequency.
+        let ratio = f64::from(to_rate) / f64::from(from_rate);
+        let kernel = Kernel::Sinc {
+            table: make_filter_table(&spec),
+            num_zero_crossings: spec.num_zero_crossings,
+            cutoff: ratio.min(1.0) * spec.bandwidth,
+            phases: Vec::new(),
+        };
+
+        let mut resampler = Self::with_kernel(kernel, from_rate, to_rate, num_channels);
+        resampler.update_phases();
+        Ok(resampler)
+    }
+
+    fn with_kernel(kernel: Kernel, from_rate: u32, to_rate: u32, num_channels: usize) -> Self {
+        let half_width = kernel.half_width();
+        let divisor = gcd(from_rate, to_rate);
+        Self {
+            kernel,
+            from_rate: u64::from(from_rate / divisor),
+            to_rate: u64::from(to_rate / divisor),
+            num_channels,
+
+            // Audio before the start is silent.
+            input: vec![0.0; half_width * num_channels],
+            position: half_width,
+            fraction: 0,
+
+            weights: Vec::new(),
+        }
+    }
+
+    /// Change the rates, keeping the buffered input and the position of the next output frame.
+    ///
+    /// The filter's cutoff is not changed, so this is meant for small adjustments,
+    /// like correcting clock drift.
+    ///
+    /// # Errors
+    /// Returns an error if the rates are zero.
+    pub fn set_rates(&mut self, from_rate: u32, to_rate: u32) -> anyhow::Result<()> {
+        if from_rate == 0 || to_rate == 0 {
+            anyhow::bail!("cannot resample from {} Hz to {} Hz", from_rate, to_rate);
+        }
+
+        let divisor = gcd(from_rate, to_rate);
+        let (from_rate, to_rate) = (u64::from(from_rate / divisor), u64::from(to_rate / divisor));
+        if (from_rate, to_rate) == (self.from_rate, self.to_rate) {
+            return Ok(());
+        }
+
+        // The fraction is less than the rate, so this fits.
+        let fraction = u128::from(self.fraction) * u128::from(to_rate) / u128::from(self.to_rate);
+        self.fraction = u64::try_from(fraction).unwrap_or(0);
+        self.from_rate = from_rate;
+        self.to_rate = to_rate;
+        self.update_phases();
+
+        Ok(())
+    }
+
+    /// Resample a chunk of interleaved input.
+    ///
+    /// Output frames are returned once all the input they depend on has arrived,
+    /// so the output lags the input by the filter's half width.
+    ///
+    /// # Errors
+    /// Returns an error if the input is not a whole number of frames.
+    pub fn process(&mut self, input: &[f32]) -> anyhow::Result<Vec<f32>> {
+        self.push(input)?;
+
+        let half_width = self.kernel.half_width();
+        let mut output = Vec::new();
+        while self.position + half_width < self.num_buffered_frames() {
+            self.interpolate(&mut output);
+        }
+        self.drop_history();
+
+        Ok(output)
+    }
+
+    /// Resample the last chunk of interleaved input, and flush the rest of the output.
+    ///
+    /// The resampler is reset afterwards.
+    ///
+    /// # Errors
+    /// Returns an error if the input is not a whole number of frames.
+    pub fn process_last(&mut self, input: &[f32]) -> anyhow::Result<Vec<f32>> {
+        self.push(input)?;
+
+        // Audio after the end is silent.
+        let end = self.num_buffered_frames();
+        let half_width = self.kernel.half_width();
+        self.input
+            .resize(self.input.len() + (half_width + 1) * self.num_channels, 0.0);
+
+        let mut output = Vec::new();
+        while self.position < end {
+            self.interpolate(&mut output);
+        }
+        self.reset();
+
+        Ok(output)
+    }
+
+    /// Drop all buffered input and history.
+    pub fn reset(&mut self) {
+        let half_width = self.kernel.half_width();
+        self.input.clear();
+        self.input.resize(half_width * self.num_channels, 0.0);
+        self.position = half_width;
+        self.fraction = 0;
+    }
+
+    /// Compute the sinc filter taps up front.
+    ///
+    /// Output frames only fall on as many fractions of an input frame as the reduced output rate.
+    /// If there are too many, the taps are computed at [`MAX_PHASES`] + 1 evenly spaced fractions
+    /// from 0 to 1 instead.
+    /// Those do not depend on the rates, so they are kept for every later rate.
+    fn update_phases(&mut self) {
+        let half_width = self.kernel.half_width();
+        let (num_phases, len) = if self.to_rate <= MAX_PHASES && !self.is_interpolated() {
+            (self.to_rate, self.to_rate)
+        } else {
+            (MAX_PHASES, MAX_PHASES + 1)
+        };
+        if let Kernel::Sinc {
+            table,
+            num_zero_crossings,
+            cutoff,
+            phases,
+        } = &mut self.kernel
+        {
+            // The same number of phases are at the same fractions.
+            if phases.len() as u64 == len {
+                return;
+            }
+
+            phases.clear();
+            phases.extend((0..len).map(|phase| {
+                let mut weights = Vec::new();
+                let fraction = phase as f64 / num_phases as f64;
+                Kernel::sinc_weights(
+                    table,
+                    *num_zero_crossings,
+                    *cutoff,
+                    half_width,
+                    fraction,
+                    &mut weights,
+                );
+                weights
+            }));
+        }
+    }
+
+    /// Return true if the sinc filter taps are interpolated between phases.
+    fn is_interpolated(&self) -> bool {
+        match &self.kernel {
+            Kernel::Sinc { phases, .. } => phases.len() as u64 == MAX_PHASES + 1,
+            _ => false,
+        }
+    }
+
+    fn num_buffered_frames(&self) -> usize {
+        self.input.len() / self.num_channels
+    }
+
+    fn push(&mut self, input: &[f32]) -> anyhow::Result<()> {
+        if input.len() % self.num_channels != 0 {
+            anyhow::bail!(
+                "got {} samples, which is not a whole number of {} channel frames",
+                input.len(),
+                self.num_channels
+            );
+        }
+        self.input.extend_from_slice(input);
+
+        Ok(())
+    }
+
+    /// Drop the buffered frames that no later output frame depends on.
+    fn drop_history(&mut self) {
+        let half_width = self.kernel.half_width();
+        let num_frames = self.position.saturating_sub(half_width);
+        self.input.drain(..num_frames * self.num_channels);
+        self.position -= num_frames;
+    }
+
+    /// Interpolate the next output frame and advance to the one after.
+    fn interpolate(&mut self, output: &mut Vec<f32>) {
+        let num_channels = self.num_channels;
+        let position = self.position;
+        let fraction = self.fraction as f64 / self.to_rate as f64;
+        let input = &self.input;
+        let frame = |index: usize| &input[index * num_channels..(index + 1) * num_channels];
+
+        match &self.kernel {
+            Kernel::ZeroOrderHold => output.extend_from_slice(frame(position)),
+            Kernel::Linear => {
+                let fraction = fraction as f32;
+                let (previous, next) = (frame(position), frame(position + 1));
+                output.extend(
+                    previous
+                        .iter()
+                        .zip(next.iter())
+                        .map(|(previous, next)| previous + (next - previous) * fraction),
+                );
+            }
+            Kernel::Sinc { phases, .. } => {
+                let half_width = self.kernel.half_width();
+                let first = position + 1 - half_width;
+
+                let weights = if !self.is_interpolated() {
+                    &phases[self.fraction as usize]
+                } else {
+                    // The fraction is less than the rate, so the phase is less than the last one.
+                    let scaled = u128::from(self.fraction) * u128::from(MAX_PHASES);
+                    let phase = (scaled / u128::from(self.to_rate)) as usize;
+                    let between =
+                        ((scaled % u128::from(self.to_rate)) as f64 / self.to_rate as f64) as f32;
+                    self.weights.clear();
+                    self.weights.extend(
+                        phases[phase]
+                            .iter()
+                            .zip(phases[phase + 1].iter())
+                            .map(|(previous, next)| previous + (next - previous) * between),
+                    );
+                    &self.weights
+                };
+
+                let taps = &input[first * num_channels..(first + 2 * half_width) * num_channels];
+                for channel in 0..num_channels {
+                    let sample: f32 = weights
+                        .iter()
+                        .zip(taps[channel..].iter().step_by(num_channels))
+                        .map(|(weight, sample)| weight * sample)
+                        .sum();
+                    output.push(sample);
+                }
+            }
+        }
+
+        self.fraction += self.from_rate;
+        let whole = self.fraction / self.to_rate;
+        self.fraction %= self.to_rate;
+        self.position += usize::try_from(whole).unwrap_or(usize::MAX);
+    }
+}
+
+/// Resample a whole buffer of interleaved audio at once.
+///
+/// # Errors
+/// Returns an error if the resampler could not be made, or the input is not a whole number of frames.
+pub fn convert(
+    quality: ResampleQuality,
+    from_rate: u32,
+    to_rate: u32,
+    num_channels: usize,
+    input: &[f32],
+) -> anyhow::Result<Vec<f32>> {
+    SincResampler::new(quality, from_rate, to_rate, num_channels)?.process_last(input)
+}
+
+/// Tabulate a Kaiser-windowed sinc filter from its centre to its last zero crossing.
+fn make_filter_table(spec: &FilterSpec) -> Vec<f64> {
+    let len = spec.num_zero_crossings * TABLE_RESOLUTION;
+    let window_scale = bessel_i0(spec.kaiser_beta);
+
+    // One extra entry, so that the last one can be interpolated towards zero.
+    (0..=len + 1)
+        .map(|index| {
+            if index >= len {
+                return 0.0;
+            }
+
+            let x = index as f64 / TABLE_RESOLUTION as f64;
+            let sinc = if index == 0 {
+                1.0
+            } else {
+                (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+            };
+            let position = x / spec.num_zero_crossings as f64;
+            let window =
+                bessel_i0(spec.kaiser_beta * (1.0 - position * position).sqrt()) / window_scale;
+
+            sinc * window
+        })
+        .collect()
+}
+
+/// Get the greatest common divisor of two numbers.
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+    a
+}
+
+/// The zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+    for k in 1..64 {
+        term *= half_x / f64::from(k);
+        sum += term * term;
+        if term * term < sum * 1e-17 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Make an interleaved stereo signal of two tones, with a different tone on each side.
+    fn make_signal(rate: u32, num_frames: u32) -> Vec<f32> {
+        (0..num_frames)
+            .flat_map(|i| {
+                let t = f64::from(i) / f64::from(rate);
+                let left = 0.5 * (2.0 * std::f64::consts::PI * 440.0 * t).sin();
+                let right = 0.25 * (2.0 * std::f64::consts::PI * 1000.0 * t).cos();
+                vec![left as f32, right as f32]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunking_does_not_change_output() {
+        let signal = make_signal(44100, 20000);
+        let expected =
+            convert(ResampleQuality::SincMedium, 44100, 48000, 2, &signal).expect("failed");
+
+        let mut resampler = SincResampler::new(ResampleQuality::SincMedium, 44100, 48000, 2)
+            .expect("failed to create resampler");
+        let mut output = Vec::new();
+        for chunk in signal.chunks(2 * 777) {
+            output.extend(resampler.process(chunk).expect("failed to resample"));
+        }
+        output.extend(resampler.process_last(&[]).expect("failed to flush"));
+
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn output_length_and_alignment() {
+        for (from_rate, to_rate) in [
+            (44100, 48000),
+            (48000, 44100),
+            (8000, 48000),
+            (22050, 44101),
+        ]
+        .iter()
+        {
+            for quality in ResampleQuality::ALL.iter() {
+                if *quality == ResampleQuality::Native {
+                    continue;
+                }
+
+                let signal = make_signal(*from_rate, *from_rate / 2);
+                let output = convert(*quality, *from_rate, *to_rate, 2, &signal).expect("failed");
+                // One output frame for every output period that starts within the input.
+                let num_frames = (from_rate / 2 * to_rate + from_rate - 1) / from_rate;
+                assert_eq!(output.len() / 2, num_frames as usize);
+
+                // Away from the edges, the output follows the tones it was resampled from.
+                let expected = make_signal(*to_rate, *to_rate / 2);
+                let tolerance = match quality {
+                    ResampleQuality::ZeroOrderHold => 0.25,
+                    ResampleQuality::Linear => 5e-2,
+                    _ => 1e-3,
+                };
+                let edge = output.len() / 10;
+                for (sample, expected) in output[edge..output.len() - edge]
+                    .iter()
+                    .zip(expected[edge..].iter())
+                {
+                    assert!(
+                        (sample - expected).abs() < tolerance,
+                        "{} from {} Hz to {} Hz: {} != {}",
+                        quality,
+                        from_rate,
+                        to_rate,
+                        sample,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dc_passes_through() {
+        let output = convert(ResampleQuality::SincBest, 48000, 44100, 1, &[0.5; 4800])
+            .expect("failed to resample");
+        for sample in output[200..output.len() - 200].iter() {
+            assert!((sample - 0.5).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn set_rates_keeps_position() {
+        // Nudge the rates around 1:1 like drift correction does, with a ramp as input.
+        let mut resampler =
+            SincResampler::new(ResampleQuality::SincMedium, 48_000_000, 48_000_000, 1)
+                .expect("failed to make resampler");
+        let input: Vec<f32> = (0..48000).map(|i| i as f32 / 48000.0).collect();
+        let mut output = Vec::new();
+        for (i, chunk) in input.chunks(480).enumerate() {
+            let to_rate = 48_000_000 + (i as u32 % 5) * 20_000;
+            resampler
+                .set_rates(48_000_000, to_rate)
+                .expect("failed to set rates");
+            output.extend(resampler.process(chunk).expect("failed to resample"));
+        }
+
+        // The output follows the ramp, only a little slower, without jumping.
+        assert!(output.len() > 48000 && output.len() < 48000 + 48000 / 400);
+        for window in output[100..output.len() - 100].windows(2) {
+            let step = window[1] - window[0];
+            assert!(
+                step > 0.5 / 48000.0 && step < 1.5 / 48000.0,
+                "stepped by {}",
+                step
+            );
+        }
+    }
+
+    #[test]
+    fn set_rates_keeps_interpolated_phases() {
+        let phases = |resampler: &SincResampler| match &resampler.kernel {
+            Kernel::Sinc { phases, .. } => phases.as_ptr(),
+            _ => unreachable!("not a sinc kernel"),
+        };
+
+        // Drift corrections have too many phases to compute, so they are interpolated.
+        let mut resampler =
+            SincResampler::new(ResampleQuality::SincMedium, 48_000_000, 48_000_001, 1)
+                .expect("failed to make resampler");
+        let interpolated = phases(&resampler);
+        resampler
+            .set_rates(48_000_000, 47_999_999)
+            .expect("failed to set rates");
+        assert_eq!(phases(&resampler), interpolated);
+
+        // They are kept for rates with few phases too, like the largest correction.
+        resampler
+            .set_rates(48_000_000, 48_096_000)
+            .expect("failed to set rates");
+        assert_eq!(phases(&resampler), interpolated);
+
+        let output = resampler
+            .process_last(&[0.5; 4800])
+            .expect("failed to resample");
+        for sample in output[200..output.len() - 200].iter() {
+            assert!((sample - 0.5).abs() < 1e-6);
+        }
+    }
+
+    /// Compare a resampled tone against the ideal one, without needing libsamplerate.
+    #[test]
+    fn resamples_a_tone_accurately() {
+        let frequency = 1000.0;
+        for (from_rate, to_rate) in [(44100, 48000), (48000, 44100), (22050, 44101)].iter() {
+            let tone: Vec<f32> = (0..*from_rate / 2)
+                .map(|i| {
+                    let phase = 2.0 * std::f64::consts::PI * frequency * f64::from(i);
+                    (0.5 * (phase / f64::from(*from_rate)).sin()) as f32
+                })
+                .collect();
+            for quality in [
+                ResampleQuality::SincBest,
+                ResampleQuality::SincMedium,
+                ResampleQuality::SincFastest,
+            ]
+            .iter()
+            {
+                let output = convert(*quality, *from_rate, *to_rate, 1, &tone).expect("failed");
+                let edge = output.len() / 10;
+                let samples = &output[edge..output.len() - edge];
+
+                // Fit the tone by least squares, with the phase of the input at the same time.
+                let omega = 2.0 * std::f64::consts::PI * frequency / f64::from(*to_rate);
+                let (mut sin_sum, mut cos_sum) = (0.0, 0.0);
+                for (i, sample) in samples.iter().enumerate() {
+                    let phase = omega * (edge + i) as f64;
+                    sin_sum += f64::from(*sample) * phase.sin();
+                    cos_sum += f64::from(*sample) * phase.cos();
+                }
+                let sin_amplitude = 2.0 * sin_sum / samples.len() as f64;
+                let cos_amplitude = 2.0 * cos_sum / samples.len() as f64;
+                let amplitude = sin_amplitude.hypot(cos_amplitude);
+                let phase = cos_amplitude.atan2(sin_amplitude);
+                let snr = crate::resample::estimate_snr(samples, frequency, *to_rate);
+
+                // The Kaiser window's passband ripple is about 10^(-A/20) for a stopband
+                // attenuation A of about 63, 90 and 117 dB at the fastest, medium and best
+                // qualities: 7e-4, 3e-5 and 1e-6, which bounds the amplitude and phase errors.
+                // The SNR floors sit below A, and below the 89 dB the fit leaks at 44101 Hz,
+                // where the samples don't hold a whole number of periods.
+                let (max_error, min_snr) = match quality {
+                    ResampleQuality::SincFastest => (1e-3, 70.0),
+                    _ => (1e-4, 85.0),
+                };
+                let label = format!("{} from {} Hz to {} Hz", quality, from_rate, to_rate);
+                assert!(
+                    (amplitude - 0.5).abs() / 0.5 < max_error,
+                    "{}: amplitude {}",
+                    label,
+                    amplitude
+                );
+                assert!(phase.abs() < max_error, "{}: phase {}", label, phase);
+                assert!(snr > min_snr, "{}: SNR {} dB", label, snr);
+            }
+        }
+    }
+
+    /// Compare against libsamplerate, which the pure-Rust resampler stands in for.
+    #[cfg(feature = "libsamplerate")]
+    #[test]
+    fn conforms_to_libsamplerate() {
+        let qualities = [
+            ResampleQuality::SincBest,
+            ResampleQuality::SincMedium,
+            ResampleQuality::SincFastest,
+        ];
+        for (from_rate, to_rate) in [(44100, 48000), (48000, 44100)].iter() {
+            let signal = make_signal(*from_rate, *from_rate / 2);
+            for quality in qualities.iter() {
+                let converter_type = quality.converter_type().expect("missing converter");
+                let expected =
+                    samplerate::convert(*from_rate, *to_rate, 2, converter_type, &signal)
+                        .expect("failed to resample with libsamplerate");
+                let output = convert(*quality, *from_rate, *to_rate, 2, &signal).expect("failed");
+
+                let len_difference = (output.len() as i64 - expected.len() as i64).abs();
+                assert!(len_difference <= 2 * 2, "{}: lengths differ", quality);
+
+                let edge = output.len() / 10;
+                let len = output.len().min(expected.len()) - edge;
+                for (sample, expected) in output[edge..len].iter().zip(expected[edge..len].iter()) {
+                    assert!(
+                        (sample - expected).abs() < 1e-3,
+                        "{} from {} Hz to {} Hz: {} != {}",
+                        quality,
+                        from_rate,
+                        to_rate,
+                        sample,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::pcm::PcmFormat;
+use symphonia::core::audio::Channels;
+
+/// An audio device that a backend can open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// The device's name, for people
+    pub name: String,
+}
+
+/// The format an [`AudioSink`] plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkFormat {
+    /// The sample rate
+    pub sample_rate: u32,
+
+    /// The speaker positions of the channels
+    pub channel_layout: Channels,
+
+    /// The layout of the samples in each frame
+    pub pcm_format: PcmFormat,
+}
+
+/// A way to find and open audio devices, like WASAPI
+pub trait AudioBackend {
+    /// The devices this backend opens
+    type Sink: AudioSink;
+
+    /// List the devices that can play audio.
+    fn devices(&self) -> anyhow::Result<Vec<DeviceInfo>>;
+
+    /// Open a device by its index in [`AudioBackend::devices`].
+    fn open(&self, index: usize) -> anyhow::Result<Self::Sink>;
+}
+
+/// An open audio device that plays from a buffer of frames
+///
+/// The whole buffer is free until the sink is started.
+pub trait AudioSink {
+    /// Get the format the device plays.
+    fn format(&self) -> SinkFormat;
+
+    /// Get the number of frames the device's buffer holds.
+    fn buffer_frames(&self) -> u32;
+
+    /// Block until the device has played some of its buffer, and get how many frames are free.
+    fn wait(&mut self) -> anyhow::Result<u32>;
+
+    /// Write frames in the device's format into the free part of the buffer.
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()>;
+
+    /// Start playing the buffer.
+    fn start(&mut self) -> anyhow::Result<()>;
+
+    /// Stop playing the buffer.
+    fn stop(&mut self) -> anyhow::Result<()>;
+}
+
+/// A sink that plays everything written to it at once, and keeps it
+#[derive(Debug)]
+pub struct MemorySink {
+    format: SinkFormat,
+    buffer_frames: u32,
+
+    is_started: bool,
+    data: Vec<u8>,
+}
+
+impl MemorySink {
+    /// Make a new [`MemorySink`] with a buffer of some frames.
+    pub fn new(format: SinkFormat, buffer_frames: u32) -> Self {
+        Self {
+            format,
+            buffer_frames,
+
+            is_started: false,
+            data: Vec::new(),
+        }
+    }
+
+    /// Get everything that was written.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Return true if the sink is playing.
+    pub fn is_started(&self) -> bool {
+        self.is_started
+    }
+}
+
+impl AudioSink for MemorySink {
+    fn format(&self) -> SinkFormat {
+        self.format
+    }
+
+    fn buffer_frames(&self) -> u32 {
+        self.buffer_frames
+    }
+
+    fn wait(&mut self) -> anyhow::Result<u32> {
+        if !self.is_started {
+            anyhow::bail!("the sink was not started");
+        }
+        Ok(self.buffer_frames)
+    }
+
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let block_align = self.format.pcm_format.block_align();
+        if data.len() % block_align != 0 {
+            anyhow::bail!(
+                "got {} bytes, which is not a whole number of {} byte frames",
+                data.len(),
+                block_align
+            );
+        }
+        if data.len() / block_align > self.buffer_frames as usize {
+            anyhow::bail!("wrote more frames than the buffer holds");
+        }
+
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn start(&mut self) -> anyhow::Result<()> {
+        self.is_started = true;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.is_started = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,509 @@
+use crate::convert::Conversion;
+use crate::convert::ConversionCache;
+use crate::convert::ConversionKey;
+use crate::decoder::AudioChunk;
+use crate::decoder::DecodeOptions;
+use crate::decoder::Decoder;
+use crate::input::Input;
+use crate::resample::ResampleQuality;
+use crate::sinc::SincResampler;
+use anyhow::Context;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// The number of decoded chunks buffered for each receiver.
+///
+/// This bounds memory use no matter how long the inputs are.
+const CHUNK_BUFFER_CAPACITY: usize = 32;
+
+/// The quality of the resampler that corrects a stream's rate for clock drift
+const RATE_CORRECTION_QUALITY: ResampleQuality = ResampleQuality::SincMedium;
+
+/// How many steps of rate correction there are per Hz
+const RATE_CORRECTION_RESOLUTION: u32 = 1000;
+
+/// Options for the decoder thread
+#[derive(Debug, Default, Clone)]
+pub struct DecoderThreadOptions {
+    /// The MIME type of the inputs, used as a hint when probing their format
+    pub mime_type: Option<String>,
+
+    /// Options for decoding each input
+    pub decode_options: DecodeOptions,
+
+    /// Where to start playing the first input on the first pass.
+    ///
+    /// Defaults to the loop start.
+    pub start: Option<Duration>,
+
+    /// Where every input starts when it loops.
+    ///
+    /// Defaults to the start of the input.
+    pub loop_start: Option<Duration>,
+
+    /// Where every input ends.
+    ///
+    /// Defaults to the end of the input.
+    pub loop_end: Option<Duration>,
+
+    /// How many times to play the inputs.
+    ///
+    /// Defaults to looping forever.
+    pub num_passes: Option<u32>,
+}
+
+/// A command for a running decoder thread
+enum DecoderCommand {
+    /// Seek within the current input
+    Seek(Duration),
+}
+
+/// A handle to control a running decoder thread
+#[derive(Clone)]
+pub struct DecoderControl {
+    sender: Sender<DecoderCommand>,
+}
+
+impl DecoderControl {
+    /// Seek within the input that is currently playing.
+    ///
+    /// Audio that was decoded before the seek is dropped by every [`AudioStream`].
+    ///
+    /// # Errors
+    /// Returns an error if the decoder thread exited.
+    pub fn seek(&self, time: Duration) -> anyhow::Result<()> {
+        self.sender
+            .send(DecoderCommand::Seek(time))
+            .ok()
+            .context("the decoder thread exited")
+    }
+}
+
+/// The receiving end of a decoder thread, for one [`AudioStream`]
+pub struct ChunkReceiver {
+    receiver: Receiver<(u64, Arc<AudioChunk>)>,
+    seek_generation: Arc<AtomicU64>,
+    conversions: ConversionCache,
+}
+
+impl ChunkReceiver {
+    /// Make a receiver that is fed by hand instead of by a decoder thread.
+    #[cfg(test)]
+    pub(crate) fn channel() -> (SyncSender<(u64, Arc<AudioChunk>)>, Self) {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(CHUNK_BUFFER_CAPACITY);
+        let receiver = Self {
+            receiver,
+            seek_generation: Arc::new(AtomicU64::new(0)),
+            conversions: ConversionCache::default(),
+        };
+        (sender, receiver)
+    }
+}
+
+/// A running decoder thread
+pub struct DecoderThread {
+    /// One receiver for every requested stream
+    pub receivers: Vec<ChunkReceiver>,
+
+    /// A handle to control the thread
+    pub control: DecoderControl,
+
+    /// The thread handle
+    pub handle: JoinHandle<anyhow::Result<()>>,
+}
+
+/// Spawn a thread that decodes the inputs in order, looping forever or for a number of passes.
+///
+/// Inputs that can only be played once, like stdin, are skipped when looping.
+/// The thread exits once no inputs are left.
+///
+/// Every receiver gets every decoded chunk.
+/// The thread blocks while any receiver's buffer is full,
+/// and exits once all receivers are dropped.
+///
+/// Inputs that cannot be opened, probed or decoded are reported on the first pass and skipped.
+/// The thread exits with an error if every input of a pass failed.
+///
+/// The decoder trims encoder delay and padding,
+/// so the end of one pass runs straight into the start of the next.
+pub fn spawn_decoder_thread(
+    inputs: Vec<Input>,
+    options: DecoderThreadOptions,
+    num_receivers: usize,
+) -> DecoderThread {
+    let seek_generation = Arc::new(AtomicU64::new(0));
+    let conversions = ConversionCache::default();
+    let (mut senders, receivers): (Vec<SyncSender<_>>, Vec<_>) = (0..num_receivers)
+        .map(|_| {
+            let (sender, receiver) = std::sync::mpsc::sync_channel(CHUNK_BUFFER_CAPACITY);
+            let receiver = ChunkReceiver {
+                receiver,
+                seek_generation: seek_generation.clone(),
+                conversions: conversions.clone(),
+            };
+            (sender, receiver)
+        })
+        .unzip();
+    let (command_sender, command_receiver) = std::sync::mpsc::channel();
+
+    let handle = std::thread::spawn(move || {
+        let mut num_passes = 0;
+        loop {
+            let is_first_pass = num_passes == 0;
+            let mut num_frames = 0;
+            let mut num_inputs = 0;
+            let mut num_failed_inputs = 0;
+            'inputs: for (i, input) in inputs.iter().enumerate() {
+                if !is_first_pass && !input.is_repeatable() {
+                    continue;
+                }
+                num_inputs += 1;
+
+                let start = if is_first_pass && i == 0 {
+                    options.start.or(options.loop_start)
+                } else {
+                    options.loop_start
+                };
+                let mut decoder = match open_decoder(input, &options, start) {
+                    Ok(decoder) => decoder,
+                    Err(e) => {
+                        if is_first_pass {
+                            eprintln!("Skipping '{}': {:?}", input, e);
+                        }
+                        num_failed_inputs += 1;
+                        continue;
+                    }
+                };
+
+                if is_first_pass {
+                    eprintln!("Playing '{}'", input);
+                    eprint!("{}", decoder.track_info());
+                }
+
+                loop {
+                    match command_receiver.try_recv() {
+                        Ok(DecoderCommand::Seek(time)) => match decoder.seek(time) {
+                            Ok(()) => {
+                                seek_generation.fetch_add(1, Ordering::SeqCst);
+                                eprintln!("Seeked to frame {} of '{}'", decoder.position(), input);
+                            }
+                            Err(e) => {
+                                eprintln!("Failed to seek in '{}': {:?}", input, e);
+                            }
+                        },
+                        Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {}
+                    }
+
+                    let chunk = match decoder.next_chunk() {
+                        Ok(Some(chunk)) => chunk,
+                        Ok(None) => break,
+                        Err(e) => {
+                            if is_first_pass {
+                                eprintln!("Skipping '{}': {:?}", input, e);
+                            }
+                            num_failed_inputs += 1;
+                            continue 'inputs;
+                        }
+                    };
+
+                    num_frames += chunk.frames();
+
+                    let chunk = (seek_generation.load(Ordering::SeqCst), Arc::new(chunk));
+                    senders.retain(|sender| sender.send(chunk.clone()).is_ok());
+                    if senders.is_empty() {
+                        return Ok(());
+                    }
+                }
+
+                let num_skipped_packets = decoder.num_skipped_packets();
+                if is_first_pass && num_skipped_packets != 0 {
+                    eprintln!(
+                        "Skipped {} undecodable packets in '{}'",
+                        num_skipped_packets, input
+                    );
+                }
+            }
+
+            if num_inputs != 0 && num_failed_inputs == num_inputs {
+                anyhow::bail!("every input failed to play");
+            }
+            if num_frames == 0 {
+                anyhow::bail!("the inputs contain no audio");
+            }
+            num_passes += 1;
+            if !inputs.iter().any(Input::is_repeatable) || Some(num_passes) == options.num_passes {
+                return Ok(());
+            }
+        }
+    });
+
+    DecoderThread {
+        receivers,
+        control: DecoderControl {
+            sender: command_sender,
+        },
+        handle,
+    }
+}
+
+/// Open an input and make a decoder for it that starts at the given time.
+fn open_decoder(
+    input: &Input,
+    options: &DecoderThreadOptions,
+    start: Option<Duration>,
+) -> anyhow::Result<Decoder> {
+    let media_source = input.open().context("failed to open")?;
+    let mut decoder = match input.raw_pcm_spec() {
+        Some(spec) => Decoder::new_raw(media_source, spec, options.decode_options.clone())?,
+        None => {
+            let hint = input.hint(options.mime_type.as_deref());
+            Decoder::new(media_source, &hint, options.decode_options.clone())?
+        }
+    };
+
+    if let Some(start) = start {
+        decoder.seek(start)?;
+    }
+    decoder.set_end(options.loop_end);
+
+    Ok(decoder)
+}
+
+/// A decoded audio stream, converted to a device's sample rate and channel count.
+///
+/// Streams from the same decoder thread with the same format share one [`Conversion`].
+pub struct AudioStream {
+    conversion: Arc<Conversion>,
+    consumer: usize,
+
+    seek_generation: u64,
+    buffer: VecDeque<f32>,
+
+    /// Resamples the converted audio by a rate correction, if one was set
+    rate_corrector: Option<SincResampler>,
+}
+
+impl AudioStream {
+    /// Make a new [`AudioStream`] from a receiver returned by [`spawn_decoder_thread`].
+    ///
+    /// The audio is converted to the device format described by the key.
+    /// If another stream from the same decoder thread already converts to this format,
+    /// its conversion is reused and the receiver is dropped.
+    pub fn new(receiver: ChunkReceiver, key: ConversionKey) -> Self {
+        let ChunkReceiver {
+            receiver,
+            seek_generation,
+            conversions,
+        } = receiver;
+        let conversion = conversions.get_or_insert_with(key.clone(), || {
+            Conversion::new(receiver, seek_generation, key)
+        });
+
+        let consumer = conversion.add_consumer();
+        Self {
+            conversion,
+            consumer,
+
+            seek_generation: 0,
+            buffer: VecDeque::new(),
+
+            rate_corrector: None,
+        }
+    }
+
+    /// Play this many device frames for each frame of audio, to make up for clock drift.
+    ///
+    /// The correction is applied from the next converted chunk.
+    /// It is a second pass after the stream's conversion,
+    /// which always uses the pure-Rust [`SincResampler`] at the `SincMedium` quality,
+    /// whatever the resampling backend and quality of the conversion.
+    ///
+    /// # Errors
+    /// Returns an error if the correction is not a small positive ratio.
+    pub fn set_rate_correction(&mut self, correction: f64) -> anyhow::Result<()> {
+        let key = self.conversion.key();
+        let from_rate = key
+            .sample_rate
+            .checked_mul(RATE_CORRECTION_RESOLUTION)
+            .context("the sample rate is too high to correct")?;
+        let to_rate = (f64::from(from_rate) * correction).round();
+        if !(1.0..=f64::from(u32::MAX)).contains(&to_rate) {
+            anyhow::bail!("cannot correct the rate by {}", correction);
+        }
+        let to_rate = to_rate as u32;
+
+        // Corrections have too many filter phases to compute,
+        // so the corrector interpolates between phases that are only computed once.
+        match self.rate_corrector.as_mut() {
+            Some(rate_corrector) => rate_corrector.set_rates(from_rate, to_rate)?,
+            None => {
+                self.rate_corrector = Some(SincResampler::new(
+                    RATE_CORRECTION_QUALITY,
+                    from_rate,
+                    to_rate,
+                    key.num_channels,
+                )?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fill the buffer with interleaved samples.
+    ///
+    /// This blocks until enough audio has been decoded.
+    /// Returns the number of frames written,
+    /// which is less than the buffer holds once the decoder thread has exited and all of its audio was read.
+    /// The rest of the buffer is then filled with silence.
+    ///
+    /// # Errors
+    /// Returns an error if resampling failed.
+    pub fn read(&mut self, buffer: &mut [f32]) -> anyhow::Result<usize> {
+        let seek_generation = self.conversion.seek_generation();
+        self.update_seek_generation(seek_generation);
+
+        while self.buffer.len() < buffer.len() {
+            let chunk = match self.conversion.next_chunk(self.consumer)? {
+                Some(chunk) => chunk,
+                None => {
+                    // Flush the end of the audio out of the rate corrector.
+                    if let Some(rate_corrector) = self.rate_corrector.as_mut() {
+                        let samples = rate_corrector
+                            .process_last(&[])
+                            .context("failed to correct the rate")?;
+                        self.buffer.extend(samples);
+                    }
+                    break;
+                }
+            };
+            self.update_seek_generation(chunk.seek_generation);
+            match self.rate_corrector.as_mut() {
+                Some(rate_corrector) => {
+                    let samples = rate_corrector
+                        .process(&chunk.samples)
+                        .context("failed to correct the rate")?;
+                    self.buffer.extend(samples);
+                }
+                None => self.buffer.extend(chunk.samples.iter()),
+            }
+        }
+
+        let len = buffer.len().min(self.buffer.len());
+        for (sample, buffered) in buffer.iter_mut().zip(self.buffer.drain(..len)) {
+            *sample = buffered;
+        }
+        for sample in buffer[len..].iter_mut() {
+            *sample = 0.0;
+        }
+
+        Ok(len / self.conversion.key().num_channels)
+    }
+
+    /// Drop buffered audio from before the latest seek.
+    fn update_seek_generation(&mut self, seek_generation: u64) {
+        if seek_generation > self.seek_generation {
+            self.seek_generation = seek_generation;
+            self.buffer.clear();
+            if let Some(rate_corrector) = self.rate_corrector.as_mut() {
+                rate_corrector.reset();
+            }
+        }
+    }
+}
+
+impl Drop for AudioStream {
+    fn drop(&mut self) {
+        self.conversion.remove_consumer(self.consumer);
+    }
+}
+
+// Decoding the test files needs their formats.
+#[cfg(all(test, feature = "mp3", feature = "wav"))]
+mod test {
+    use super::*;
+    use crate::decoder::DecodeErrorPolicy;
+    use crate::pcm::PcmFormat;
+    use crate::raw::RawSampleFormat;
+    use crate::sink::AudioSink;
+    use crate::sink::SinkFormat;
+    use crate::wav::WavSink;
+    use std::io::Read;
+    use std::path::Path;
+    use symphonia::core::audio::Channels;
+
+    /// Write a WAVE file of 1000 mono frames of one value.
+    fn write_wav(path: &Path, value: f32) {
+        let format = SinkFormat {
+            sample_rate: 8000,
+            channel_layout: Channels::FRONT_CENTRE,
+            pcm_format: PcmFormat::packed(RawSampleFormat::F32Le, 1).expect("invalid format"),
+        };
+        let mut sink = WavSink::create(path, format).expect("failed to create file");
+        let data: Vec<u8> = std::iter::repeat(value.to_le_bytes())
+            .take(1000)
+            .flatten()
+            .collect();
+        sink.write(&data).expect("failed to write");
+        sink.stop().expect("failed to stop");
+    }
+
+    #[test]
+    fn skips_inputs_that_fail_to_decode() {
+        let dir = std::env::temp_dir().join(format!("donacdum-stream-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("failed to make directory");
+        let first = dir.join("first.wav");
+        let corrupt = dir.join("corrupt.mp3");
+        let last = dir.join("last.wav");
+        write_wav(&first, 0.25);
+        write_wav(&last, 0.5);
+
+        // Garbage in the middle of the clip fails to decode part of the way through.
+        let mut mp3 = Vec::new();
+        Input::Embedded
+            .open()
+            .expect("failed to open")
+            .read_to_end(&mut mp3)
+            .expect("failed to read");
+        mp3.truncate(200_000);
+        for byte in mp3[100_000..120_000].iter_mut() {
+            *byte = 0x55;
+        }
+        std::fs::write(&corrupt, &mp3).expect("failed to write");
+
+        let inputs = vec![Input::File(first), Input::File(corrupt), Input::File(last)];
+        let options = DecoderThreadOptions {
+            decode_options: DecodeOptions {
+                error_policy: DecodeErrorPolicy::Abort,
+                ..DecodeOptions::default()
+            },
+            num_passes: Some(1),
+            ..DecoderThreadOptions::default()
+        };
+        let mut decoder_thread = spawn_decoder_thread(inputs, options, 1);
+        let receiver = decoder_thread.receivers.remove(0);
+
+        // Both good files play in full, in order.
+        let mut values = Vec::new();
+        for (_, chunk) in receiver.receiver.iter() {
+            values.extend(chunk.samples.iter().filter(|sample| **sample > 0.2));
+        }
+        let result = decoder_thread
+            .handle
+            .join()
+            .expect("decoder thread panicked");
+        std::fs::remove_dir_all(&dir).expect("failed to remove directory");
+
+        result.expect("failed to decode");
+        let num_first = values.iter().take_while(|value| **value == 0.25).count();
+        assert_eq!(num_first, 1000);
+        assert!(values[num_first..].ends_with(&[0.5; 1000]));
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+/// Parse a timestamp in the form `[[hh:]mm:]ss[.fff]`.
+///
+/// Minutes and seconds may be larger than 59 if they are the first field,
+/// so `90` and `1:30` are the same timestamp.
+pub fn parse_timestamp(value: &str) -> Result<Duration, String> {
+    let mut fields = value.rsplit(':');
+
+    let seconds = fields.next().unwrap_or_default();
+    let (seconds, nanos) = parse_seconds(seconds)
+        .ok_or_else(|| format!("invalid seconds in timestamp '{}'", value))?;
+
+    let mut whole_seconds = seconds;
+    for (multiplier, field) in [60, 60 * 60].iter().zip(&mut fields) {
+        let field = field
+            .parse::<u64>()
+            .map_err(|_| format!("invalid field '{}' in timestamp '{}'", field, value))?;
+        whole_seconds += field * multiplier;
+    }
+
+    if fields.next().is_some() {
+        return Err(format!("too many fields in timestamp '{}'", value));
+    }
+
+    Ok(Duration::new(whole_seconds, nanos))
+}
+
+/// Parse seconds in the form `ss[.fff]` into whole seconds and nanoseconds.
+fn parse_seconds(value: &str) -> Option<(u64, u32)> {
+    let (seconds, fraction) = match value.find('.') {
+        Some(index) => (&value[..index], &value[index + 1..]),
+        None => (value, "0"),
+    };
+
+    let is_digits = |value: &str| value.bytes().all(|b| b.is_ascii_digit());
+    if seconds.is_empty()
+        || fraction.is_empty()
+        || fraction.len() > 9
+        || !is_digits(seconds)
+        || !is_digits(fraction)
+    {
+        return None;
+    }
+
+    let seconds = seconds.parse().ok()?;
+    let nanos = format!("{:0<9}", fraction).parse().ok()?;
+
+    Some((seconds, nanos))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valid() {
+        assert_eq!(parse_timestamp("0"), Ok(Duration::from_secs(0)));
+        assert_eq!(parse_timestamp("3.2"), Ok(Duration::from_millis(3200)));
+        assert_eq!(parse_timestamp("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_timestamp("0:07.8"), Ok(Duration::from_millis(7800)));
+        assert_eq!(parse_timestamp("1:30"), Ok(Duration::from_secs(90)));
+        assert_eq!(
+            parse_timestamp("1:00:01.5"),
+            Ok(Duration::from_millis(3_601_500))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        for value in [
+            "",
+            "-1",
+            "a",
+            "1:",
+            ":1",
+            "1:a:1",
+            "1:1:1:1",
+            "1.",
+            "1.2.3",
+            "1.0000000001",
+        ]
+        .iter()
+        {
+            assert!(parse_timestamp(value).is_err(), "'{}' parsed", value);
+        }
+    }
+}
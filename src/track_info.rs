@@ -0,0 +1,273 @@
+use crate::decoder::ts_to_frames;
+use std::fmt;
+use std::time::Duration;
+use symphonia::core::codecs::CodecParameters;
+use symphonia::core::formats::Track;
+use symphonia::core::meta::StandardTagKey;
+use symphonia::core::meta::Tag;
+use symphonia::core::meta::Value;
+
+/// ReplayGain values embedded in a track's tags
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReplayGain {
+    /// The gain to apply to this track, in dB
+    pub track_gain: Option<f32>,
+
+    /// The peak amplitude of this track, where 1.0 is full scale
+    pub track_peak: Option<f32>,
+
+    /// The gain to apply to the album this track is on, in dB
+    pub album_gain: Option<f32>,
+
+    /// The peak amplitude of the album this track is on, where 1.0 is full scale
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    /// Returns `true` if no ReplayGain values are present.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Information about a track
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    /// The id of the track in its container
+    pub id: u32,
+
+    /// The language of the track
+    pub language: Option<String>,
+
+    /// The track title
+    pub title: Option<String>,
+
+    /// The track artist
+    pub artist: Option<String>,
+
+    /// The album the track is on
+    pub album: Option<String>,
+
+    /// Embedded ReplayGain values
+    pub replay_gain: ReplayGain,
+
+    /// The exact number of frames in the track, if known.
+    ///
+    /// This excludes encoder delay and padding.
+    pub num_frames: Option<u64>,
+
+    /// The codec parameters of the track
+    pub codec_params: CodecParameters,
+}
+
+impl TrackInfo {
+    /// Make a new [`TrackInfo`] from a track and the tags of its container.
+    ///
+    /// Later tags override earlier ones with the same key.
+    pub fn new<'a, I>(track: &Track, tags: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Tag>,
+    {
+        let codec_params = &track.codec_params;
+        let mut info = Self {
+            id: track.id,
+            language: track.language.clone(),
+            title: None,
+            artist: None,
+            album: None,
+            replay_gain: ReplayGain::default(),
+            num_frames: codec_params.n_frames.and_then(|n_frames| {
+                let rate = codec_params.sample_rate?;
+                Some(ts_to_frames(codec_params.time_base, n_frames, rate))
+            }),
+            codec_params: codec_params.clone(),
+        };
+
+        for tag in tags {
+            let std_key = match tag.std_key {
+                Some(std_key) => std_key,
+                None => continue,
+            };
+
+            match std_key {
+                StandardTagKey::TrackTitle => info.title = Some(tag.value.to_string()),
+                StandardTagKey::Artist => info.artist = Some(tag.value.to_string()),
+                StandardTagKey::Album => info.album = Some(tag.value.to_string()),
+                StandardTagKey::ReplayGainTrackGain => {
+                    info.replay_gain.track_gain = parse_replay_gain_value(&tag.value);
+                }
+                StandardTagKey::ReplayGainTrackPeak => {
+                    info.replay_gain.track_peak = parse_replay_gain_value(&tag.value);
+                }
+                StandardTagKey::ReplayGainAlbumGain => {
+                    info.replay_gain.album_gain = parse_replay_gain_value(&tag.value);
+                }
+                StandardTagKey::ReplayGainAlbumPeak => {
+                    info.replay_gain.album_peak = parse_replay_gain_value(&tag.value);
+                }
+                _ => {}
+            }
+        }
+
+        info
+    }
+
+    /// Get the exact duration of the track, if known.
+    pub fn duration(&self) -> Option<Duration> {
+        let num_frames = self.num_frames?;
+        let rate = u64::from(self.codec_params.sample_rate?);
+
+        let seconds = num_frames / rate;
+        let nanos = (num_frames % rate) * 1_000_000_000 / rate;
+        Some(Duration::new(seconds, nanos as u32))
+    }
+
+    /// Get the short name of the track's codec, if it is supported.
+    pub fn codec_name(&self) -> Option<&'static str> {
+        symphonia::default::get_codecs()
+            .get_codec(self.codec_params.codec)
+            .map(|descriptor| descriptor.short_name)
+    }
+}
+
+impl fmt::Display for TrackInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unknown = "<unknown>";
+
+        writeln!(f, "Title: {}", self.title.as_deref().unwrap_or(unknown))?;
+        writeln!(f, "Artist: {}", self.artist.as_deref().unwrap_or(unknown))?;
+        writeln!(f, "Album: {}", self.album.as_deref().unwrap_or(unknown))?;
+        writeln!(
+            f,
+            "Language: {}",
+            self.language.as_deref().unwrap_or(unknown)
+        )?;
+        writeln!(f, "Codec: {}", self.codec_name().unwrap_or(unknown))?;
+
+        match self.codec_params.sample_rate {
+            Some(sample_rate) => writeln!(f, "Hertz: {}", sample_rate)?,
+            None => writeln!(f, "Hertz: {}", unknown)?,
+        }
+        match self.codec_params.channels {
+            Some(channels) => {
+                writeln!(f, "# of Channels: {}", channels.count())?;
+                writeln!(f, "Channel Layout: {:?}", channels)?;
+            }
+            None => writeln!(f, "# of Channels: {}", unknown)?,
+        }
+
+        match (self.num_frames, self.duration()) {
+            (Some(num_frames), Some(duration)) => {
+                let seconds = duration.as_secs();
+                writeln!(
+                    f,
+                    "Duration: {}:{:02}.{:03} ({} frames)",
+                    seconds / 60,
+                    seconds % 60,
+                    duration.subsec_millis(),
+                    num_frames
+                )?;
+            }
+            _ => writeln!(f, "Duration: {}", unknown)?,
+        }
+
+        if !self.replay_gain.is_empty() {
+            let format_value = |value: Option<f32>, unit| match value {
+                Some(value) => format!("{:.2}{}", value, unit),
+                None => unknown.to_string(),
+            };
+
+            writeln!(
+                f,
+                "ReplayGain: track {} (peak {}), album {} (peak {})",
+                format_value(self.replay_gain.track_gain, " dB"),
+                format_value(self.replay_gain.track_peak, ""),
+                format_value(self.replay_gain.album_gain, " dB"),
+                format_value(self.replay_gain.album_peak, ""),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a ReplayGain tag value, like `-6.54 dB` or `0.988`.
+fn parse_replay_gain_value(value: &Value) -> Option<f32> {
+    match value {
+        Value::Float(value) => Some(*value as f32),
+        Value::String(value) => value
+            .trim()
+            .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+            .trim()
+            .parse()
+            .ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use symphonia::core::codecs::CODEC_TYPE_MP3;
+    use symphonia::core::units::TimeBase;
+
+    #[test]
+    fn tags() {
+        let tags = [
+            Tag::new(
+                Some(StandardTagKey::TrackTitle),
+                "TITLE",
+                "Old Title".into(),
+            ),
+            Tag::new(Some(StandardTagKey::TrackTitle), "TITLE", "Title".into()),
+            Tag::new(Some(StandardTagKey::Artist), "ARTIST", "Artist".into()),
+            Tag::new(None, "ALBUM", "Not An Album".into()),
+            Tag::new(
+                Some(StandardTagKey::ReplayGainTrackGain),
+                "REPLAYGAIN_TRACK_GAIN",
+                "-6.54 dB".into(),
+            ),
+            Tag::new(
+                Some(StandardTagKey::ReplayGainTrackPeak),
+                "REPLAYGAIN_TRACK_PEAK",
+                "0.988".into(),
+            ),
+            Tag::new(
+                Some(StandardTagKey::ReplayGainAlbumGain),
+                "REPLAYGAIN_ALBUM_GAIN",
+                "invalid".into(),
+            ),
+        ];
+
+        let info = TrackInfo::new(&Track::new(0, CodecParameters::new()), tags.iter());
+
+        assert_eq!(info.title.as_deref(), Some("Title"));
+        assert_eq!(info.artist.as_deref(), Some("Artist"));
+        assert_eq!(info.album, None);
+        assert_eq!(
+            info.replay_gain,
+            ReplayGain {
+                track_gain: Some(-6.54),
+                track_peak: Some(0.988),
+                album_gain: None,
+                album_peak: None,
+            }
+        );
+    }
+
+    #[test]
+    fn duration() {
+        let mut codec_params = CodecParameters::new();
+        codec_params
+            .for_codec(CODEC_TYPE_MP3)
+            .with_sample_rate(44100)
+            .with_time_base(TimeBase::new(1, 44100))
+            .with_n_frames(66150);
+
+        let info = TrackInfo::new(&Track::new(0, codec_params), &[]);
+
+        assert_eq!(info.num_frames, Some(66150));
+        assert_eq!(info.duration(), Some(Duration::from_millis(1500)));
+        assert_eq!(info.codec_name(), Some("mp3"));
+    }
+}
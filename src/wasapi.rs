@@ -0,0 +1,274 @@
+use anyhow::Context;
+use donacdum::mix::default_layout;
+use donacdum::pcm::PcmEncoding;
+use donacdum::pcm::PcmFormat;
+use donacdum::sink::AudioBackend;
+use donacdum::sink::AudioSink;
+use donacdum::sink::DeviceInfo;
+use donacdum::sink::SinkFormat;
+use std::convert::TryFrom;
+use std::os::windows::raw::HANDLE;
+use symphonia::core::audio::Channels;
+use win_core_audio::AudioClient;
+use win_core_audio::AudioClientShareMode;
+use win_core_audio::AudioRenderClient;
+use win_core_audio::DataFlow;
+use win_core_audio::DeviceState;
+use win_core_audio::KsDataFormatType;
+use win_core_audio::MultiMediaDevice;
+use win_core_audio::MultiMediaDeviceCollection;
+use win_core_audio::MultiMediaDeviceEnumerator;
+use win_core_audio::StorageAccessMode;
+use win_core_audio::WaveFormatExtensible;
+use win_core_audio::WaveFormatType;
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::winerror::FAILED;
+use winapi::um::combaseapi::CoInitializeEx;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::objbase::COINIT_APARTMENTTHREADED;
+use winapi::um::synchapi::CreateEventW;
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::INFINITE;
+use winapi::um::winbase::WAIT_FAILED;
+
+pub fn init_sta_com_runtime() -> std::io::Result<()> {
+    let code = unsafe { CoInitializeEx(std::ptr::null_mut(), COINIT_APARTMENTTHREADED) };
+    if FAILED(code) {
+        return Err(std::io::Error::from_raw_os_error(code));
+    }
+    Ok(())
+}
+
+pub struct Event(HANDLE);
+
+impl Event {
+    pub fn new() -> std::io::Result<Self> {
+        let handle =
+            unsafe { CreateEventW(std::ptr::null_mut(), FALSE, FALSE, std::ptr::null_mut()) };
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(Self(handle.cast()))
+    }
+
+    /// Block until the event is signaled.
+    pub fn wait(&self) -> std::io::Result<()> {
+        let ret = unsafe { WaitForSingleObject(self.0.cast(), INFINITE) };
+        if ret == WAIT_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0.cast());
+        }
+    }
+}
+
+/// Get the sample layout a device's format describes.
+fn pcm_format(format: &WaveFormatExtensible) -> anyhow::Result<PcmFormat> {
+    let encoding = match (format.wave_format_type(), format.ks_data_format_type()) {
+        (Ok(WaveFormatType::Pcm), _) | (_, Some(Ok(KsDataFormatType::Pcm))) => PcmEncoding::Int,
+        (Ok(WaveFormatType::Float), _) | (_, Some(Ok(KsDataFormatType::Float))) => {
+            PcmEncoding::Float
+        }
+        _ => anyhow::bail!("unsupported device format {:?}", format),
+    };
+
+    // Some drivers leave the valid bits unset.
+    let bits_per_sample = format.bits_per_sample();
+    let valid_bits_per_sample = format
+        .valid_bits_per_sample()
+        .filter(|valid_bits_per_sample| *valid_bits_per_sample != 0)
+        .unwrap_or(bits_per_sample);
+
+    PcmFormat::new(
+        encoding,
+        bits_per_sample,
+        valid_bits_per_sample,
+        usize::from(format.num_channels()),
+        usize::from(format.block_align()),
+    )
+}
+
+/// The active render devices, through WASAPI.
+///
+/// COM objects belong to the thread that made them, so every device thread makes its own backend.
+pub struct WasapiBackend {
+    audio_devices_collection: MultiMediaDeviceCollection,
+}
+
+impl WasapiBackend {
+    /// Make a new [`WasapiBackend`] for this thread.
+    pub fn new() -> anyhow::Result<Self> {
+        init_sta_com_runtime().context("failed to init com runtime")?;
+
+        let device_enumerator =
+            MultiMediaDeviceEnumerator::new().context("failed to create device enumerator")?;
+
+        let audio_devices_collection = device_enumerator
+            .enum_audio_endpoints(DataFlow::Render, DeviceState::ACTIVE)
+            .context("failed to enumerate audio endpoints")?;
+
+        Ok(Self {
+            audio_devices_collection,
+        })
+    }
+
+    fn device(&self, index: usize) -> anyhow::Result<MultiMediaDevice> {
+        let index = u32::try_from(index).context("invalid device index")?;
+        self.audio_devices_collection
+            .item(index)
+            .context("failed to get audio device")
+    }
+}
+
+impl AudioBackend for WasapiBackend {
+    type Sink = WasapiSink;
+
+    fn devices(&self) -> anyhow::Result<Vec<DeviceInfo>> {
+        let num_audio_devices = self
+            .audio_devices_collection
+            .get_count()
+            .context("failed to get # of audio devices")?;
+
+        (0..num_audio_devices as usize)
+            .map(|index| {
+                let audio_device = self.device(index)?;
+                let name = audio_device
+                    .open_property_store(StorageAccessMode::READ)
+                    .and_then(|property_store| {
+                        property_store.get_value(MultiMediaDevice::DEVICE_FRIENDLY_NAME)
+                    })
+                    .ok()
+                    .and_then(|value| value.as_wide_string().map(|name| name.to_string_lossy()))
+                    .unwrap_or_else(|| format!("Audio device {}", index));
+
+                Ok(DeviceInfo { name })
+            })
+            .collect()
+    }
+
+    fn open(&self, index: usize) -> anyhow::Result<WasapiSink> {
+        let audio_device = self.device(index)?;
+
+        let audio_client = audio_device
+            .activate_audio_client()
+            .context("failed to get audio client")?;
+
+        let (_default_period, minimum_period) = audio_client
+            .get_device_period()
+            .context("failed to get device period")?;
+
+        let mix_format = audio_client
+            .get_mix_format()
+            .context("failed to get mix format")?;
+
+        let pcm_format = pcm_format(&mix_format)?;
+        let channel_layout = mix_format
+            .channel_mask()
+            .filter(|channel_mask| *channel_mask != 0)
+            .map(Channels::from_bits_truncate)
+            .unwrap_or_else(|| default_layout(pcm_format.num_channels()));
+        let format = SinkFormat {
+            sample_rate: mix_format.samples_per_sec(),
+            channel_layout,
+            pcm_format,
+        };
+
+        let share_mode = AudioClientShareMode::Shared;
+        audio_client
+            .initialize(share_mode, minimum_period, minimum_period, &mix_format)
+            .context("failed to initialize audio client")?;
+
+        let event = Event::new().context("failed to make event handle")?;
+        audio_client
+            .set_event_handle(event.0.cast())
+            .context("failed to set event handle")?;
+
+        let buffer_frames = audio_client
+            .get_buffer_size()
+            .context("failed to get buffer size")?;
+
+        let render_client = audio_client
+            .get_service_audio_render_client()
+            .context("failed to get render client")?;
+
+        Ok(WasapiSink {
+            audio_client,
+            render_client,
+            event,
+            format,
+            buffer_frames,
+        })
+    }
+}
+
+/// A shared-mode WASAPI render device, signaled by an event every period
+pub struct WasapiSink {
+    audio_client: AudioClient,
+    render_client: AudioRenderClient,
+    event: Event,
+
+    format: SinkFormat,
+    buffer_frames: u32,
+}
+
+impl AudioSink for WasapiSink {
+    fn format(&self) -> SinkFormat {
+        self.format
+    }
+
+    fn buffer_frames(&self) -> u32 {
+        self.buffer_frames
+    }
+
+    fn wait(&mut self) -> anyhow::Result<u32> {
+        self.event.wait().context("failed to wait for event")?;
+
+        let buffer_frames = self
+            .audio_client
+            .get_buffer_size()
+            .context("failed to get buffer size")?;
+        let current_padding = self
+            .audio_client
+            .get_current_padding()
+            .context("failed to get current padding")?;
+
+        Ok(buffer_frames.saturating_sub(current_padding))
+    }
+
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let num_frames = u32::try_from(data.len() / self.format.pcm_format.block_align())
+            .context("too many frames")?;
+        if num_frames == 0 {
+            return Ok(());
+        }
+
+        unsafe {
+            let ptr = self
+                .render_client
+                .get_buffer(num_frames)
+                .context("failed to get buffer")?;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+            self.render_client
+                .release_buffer(num_frames)
+                .context("failed to release buffer")?;
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) -> anyhow::Result<()> {
+        self.audio_client.start().context("failed to start")
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.audio_client.stop().context("failed to stop")
+    }
+}
@@ -0,0 +1,297 @@
+use crate::pcm::PcmEncoding;
+use crate::sink::AudioSink;
+use crate::sink::SinkFormat;
+use anyhow::Context;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+
+/// The number of frames a [`WavSink`] takes at once
+const BUFFER_FRAMES: u32 = 4096;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// The tail of the KSDATAFORMAT_SUBTYPE GUIDs, after the wave format tag in the first 4 bytes
+const SUBTYPE_GUID_TAIL: [u8; 12] = [
+    0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+const SUBTYPE_PCM: u32 = 0x0001;
+const SUBTYPE_IEEE_FLOAT: u32 = 0x0003;
+
+/// A sink that writes a RIFF/WAVE file as fast as it is fed.
+///
+/// WAVE_FORMAT_EXTENSIBLE is used for float samples, more than 2 channels,
+/// or samples with padding bits, as the plain header cannot describe them.
+/// The header's sizes are filled in when the sink is stopped.
+pub struct WavSink<W> {
+    writer: W,
+    format: SinkFormat,
+
+    num_data_bytes: u64,
+}
+
+impl WavSink<BufWriter<File>> {
+    /// Create a WAVE file.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be created.
+    pub fn create(path: &Path, format: SinkFormat) -> anyhow::Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("failed to create '{}'", path.display()))?;
+        Self::new(BufWriter::new(file), format)
+    }
+}
+
+impl<W> WavSink<W>
+where
+    W: Write + Seek,
+{
+    /// Make a new [`WavSink`] that writes to a writer.
+    ///
+    /// # Errors
+    /// Returns an error if the header could not be written.
+    pub fn new(writer: W, format: SinkFormat) -> anyhow::Result<Self> {
+        let mut sink = Self {
+            writer,
+            format,
+
+            num_data_bytes: 0,
+        };
+        sink.write_header()?;
+
+        Ok(sink)
+    }
+
+    /// Get the writer back, after finishing the file.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be finished.
+    pub fn into_inner(mut self) -> anyhow::Result<W> {
+        self.finish()?;
+        Ok(self.writer)
+    }
+
+    /// Return true if the plain header cannot describe the format.
+    fn is_extensible(&self) -> bool {
+        let pcm_format = self.format.pcm_format;
+        pcm_format.encoding() == PcmEncoding::Float
+            || pcm_format.num_channels() > 2
+            || pcm_format.valid_bits_per_sample() != pcm_format.bits_per_sample()
+    }
+
+    /// Write the header at the start of the file, with the sizes of the data written so far.
+    fn write_header(&mut self) -> anyhow::Result<()> {
+        let pcm_format = self.format.pcm_format;
+        let is_extensible = self.is_extensible();
+
+        let fmt_len: u32 = if is_extensible { 40 } else { 16 };
+        let data_len =
+            u32::try_from(self.num_data_bytes).context("the file is too big for a WAVE file")?;
+        let pad_len = self.num_data_bytes % 2;
+        let riff_len =
+            u32::try_from(u64::from(4 + 8 + fmt_len + 8) + self.num_data_bytes + pad_len)
+                .context("the file is too big for a WAVE file")?;
+
+        let num_channels = u16::try_from(pcm_format.num_channels()).context("too many channels")?;
+        let block_align = u16::try_from(pcm_format.block_align()).context("frames too big")?;
+        let bytes_per_sec = self
+            .format
+            .sample_rate
+            .checked_mul(u32::from(block_align))
+            .context("the sample rate is too high")?;
+
+        let mut header = Vec::with_capacity(68);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&riff_len.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&fmt_len.to_le_bytes());
+        let format_tag = if is_extensible {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            WAVE_FORMAT_PCM
+        };
+        header.extend_from_slice(&format_tag.to_le_bytes());
+        header.extend_from_slice(&num_channels.to_le_bytes());
+        header.extend_from_slice(&self.format.sample_rate.to_le_bytes());
+        header.extend_from_slice(&bytes_per_sec.to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&pcm_format.bits_per_sample().to_le_bytes());
+        if is_extensible {
+            let subtype = match pcm_format.encoding() {
+                PcmEncoding::Int => SUBTYPE_PCM,
+                PcmEncoding::Float => SUBTYPE_IEEE_FLOAT,
+            };
+
+            header.extend_from_slice(&22_u16.to_le_bytes());
+            header.extend_from_slice(&pcm_format.valid_bits_per_sample().to_le_bytes());
+            header.extend_from_slice(&self.format.channel_layout.bits().to_le_bytes());
+            header.extend_from_slice(&subtype.to_le_bytes());
+            header.extend_from_slice(&SUBTYPE_GUID_TAIL);
+        }
+
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_len.to_le_bytes());
+
+        self.writer
+            .write_all(&header)
+            .context("failed to write WAVE header")
+    }
+
+    /// Pad the data to a whole number of words, and fill in the header's sizes.
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let end = self
+            .writer
+            .stream_position()
+            .context("failed to get the end of the file")?;
+        if self.num_data_bytes % 2 != 0 {
+            self.writer
+                .write_all(&[0])
+                .context("failed to pad WAVE data")?;
+        }
+
+        self.writer
+            .seek(SeekFrom::Start(0))
+            .context("failed to seek to WAVE header")?;
+        self.write_header()?;
+
+        // Later writes overwrite the padding.
+        self.writer
+            .seek(SeekFrom::Start(end))
+            .context("failed to seek to the end of the file")?;
+        self.writer.flush().context("failed to flush WAVE file")
+    }
+}
+
+impl<W> AudioSink for WavSink<W>
+where
+    W: Write + Seek,
+{
+    fn format(&self) -> SinkFormat {
+        self.format
+    }
+
+    fn buffer_frames(&self) -> u32 {
+        BUFFER_FRAMES
+    }
+
+    fn wait(&mut self) -> anyhow::Result<u32> {
+        Ok(BUFFER_FRAMES)
+    }
+
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.writer
+            .write_all(data)
+            .context("failed to write WAVE data")?;
+        self.num_data_bytes += data.len() as u64;
+        Ok(())
+    }
+
+    fn start(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pcm::PcmFormat;
+    use crate::raw::RawSampleFormat;
+    use std::io::Cursor;
+    use symphonia::core::audio::Channels;
+
+    fn write_wav(format: SinkFormat, data: &[u8]) -> Vec<u8> {
+        let mut sink = WavSink::new(Cursor::new(Vec::new()), format).expect("failed to make sink");
+        sink.start().expect("failed to start");
+        sink.write(data).expect("failed to write");
+        sink.stop().expect("failed to stop");
+        sink.into_inner().expect("failed to finish").into_inner()
+    }
+
+    #[test]
+    fn plain_header() {
+        let format = SinkFormat {
+            sample_rate: 44100,
+            channel_layout: Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+            pcm_format: PcmFormat::packed(RawSampleFormat::S16Le, 2).expect("invalid format"),
+        };
+        let file = write_wav(format, &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(b"RIFF");
+        expected.extend_from_slice(&44_u32.to_le_bytes());
+        expected.extend_from_slice(b"WAVEfmt ");
+        expected.extend_from_slice(&16_u32.to_le_bytes());
+        expected.extend_from_slice(&[0x01, 0x00, 0x02, 0x00]);
+        expected.extend_from_slice(&44100_u32.to_le_bytes());
+        expected.extend_from_slice(&(44100_u32 * 4).to_le_bytes());
+        expected.extend_from_slice(&[0x04, 0x00, 0x10, 0x00]);
+        expected.extend_from_slice(b"data");
+        expected.extend_from_slice(&8_u32.to_le_bytes());
+        expected.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(file, expected);
+    }
+
+    #[test]
+    fn extensible_header() {
+        // 5.1 float
+        let channel_layout = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+        let format = SinkFormat {
+            sample_rate: 48000,
+            channel_layout,
+            pcm_format: PcmFormat::packed(RawSampleFormat::F32Le, 6).expect("invalid format"),
+        };
+        let file = write_wav(format, &[0; 24]);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(b"RIFF");
+        expected.extend_from_slice(&(4 + 48 + 8 + 24_u32).to_le_bytes());
+        expected.extend_from_slice(b"WAVEfmt ");
+        expected.extend_from_slice(&40_u32.to_le_bytes());
+        expected.extend_from_slice(&[0xFE, 0xFF, 0x06, 0x00]);
+        expected.extend_from_slice(&48000_u32.to_le_bytes());
+        expected.extend_from_slice(&(48000_u32 * 24).to_le_bytes());
+        expected.extend_from_slice(&[0x18, 0x00, 0x20, 0x00]);
+        expected.extend_from_slice(&[0x16, 0x00, 0x20, 0x00]);
+        expected.extend_from_slice(&0x3F_u32.to_le_bytes());
+        expected.extend_from_slice(&[
+            0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38,
+            0x9B, 0x71,
+        ]);
+        expected.extend_from_slice(b"data");
+        expected.extend_from_slice(&24_u32.to_le_bytes());
+        expected.extend_from_slice(&[0; 24]);
+        assert_eq!(file, expected);
+    }
+
+    #[test]
+    fn odd_data_is_padded() {
+        let format = SinkFormat {
+            sample_rate: 8000,
+            channel_layout: Channels::FRONT_CENTRE,
+            pcm_format: PcmFormat::packed(RawSampleFormat::S24Le, 1).expect("invalid format"),
+        };
+        let file = write_wav(format, &[1, 2, 3]);
+
+        // The data chunk keeps its real size, the RIFF chunk counts the padding.
+        assert_eq!(file.len(), 44 + 4);
+        assert_eq!(file[4..8], 40_u32.to_le_bytes());
+        assert_eq!(file[40..44], 3_u32.to_le_bytes());
+        assert_eq!(file[44..], [1, 2, 3, 0]);
+    }
+}